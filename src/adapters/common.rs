@@ -1,5 +1,9 @@
-use std::time::Instant;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
 use anyhow::Result;
+use regex::Regex;
 
 #[derive(Default, Debug)]
 pub enum Direction {
@@ -8,34 +12,137 @@ pub enum Direction {
     Incoming,
 }
 
-use crate::json::JsonFormatter;
+use crate::binary;
+use crate::json::{JsonError, JsonFormatter, JsonValue};
+use crate::time;
+
+static NEXT_CONN_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Identifies a single connection across all adapters, e.g. `ws#3`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConnId(String);
+
+impl ConnId {
+    pub fn next(kind: &'static str) -> Self {
+        ConnId(format!("{}#{}", kind, NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+impl From<&str> for ConnId {
+    fn from(s: &str) -> Self {
+        ConnId(String::from(s.trim_start_matches('@')))
+    }
+}
+
+impl Display for ConnId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub id: ConnId,
+    pub kind: &'static str,
+    pub peer: Option<SocketAddr>,
+    pub connected_at: SystemTime,
+    pub received: usize,
+    pub sent: usize,
+    /// request path of the WebSocket handshake
+    pub path: Option<String>,
+    /// subprotocol selected in the WebSocket handshake
+    pub protocol: Option<String>,
+}
+
+impl ConnectionInfo {
+    pub fn new(kind: &'static str, peer: Option<SocketAddr>) -> Self {
+        ConnectionInfo {
+            id: ConnId::next(kind),
+            kind,
+            peer,
+            connected_at: SystemTime::now(),
+            received: 0,
+            sent: 0,
+            path: None,
+            protocol: None,
+        }
+    }
+}
+
+impl Display for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.peer {
+            Some(peer) => write!(f, "{} {}", self.id, peer),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+/// A stream accepted by an adapter, together with its identity.
+#[derive(Debug)]
+pub struct Connection<S> {
+    pub info: ConnectionInfo,
+    pub stream: S,
+}
+
+impl<S> Connection<S> {
+    pub fn new(kind: &'static str, peer: Option<SocketAddr>, stream: S) -> Self {
+        Connection {
+            info: ConnectionInfo::new(kind, peer),
+            stream,
+        }
+    }
+
+    /// Whether a message addressed to `target` should go to this connection.
+    /// No target means broadcast.
+    pub fn is_target(&self, target: Option<&ConnId>) -> bool {
+        target.map(|t| *t == self.info.id).unwrap_or(true)
+    }
+}
 
 #[derive(Debug)]
 pub struct Line {
-    pub timestamp: Instant,
+    pub timestamp: SystemTime,
     pub text: String,
     pub treat_as_json: bool,
     pub invalid_json: bool,
     pub dir: Direction,
+    pub conn: Option<ConnId>,
+    /// payload as it went over the wire, only set for messages
+    pub raw: Option<String>,
+    /// why the payload is not valid json
+    pub json_error: Option<JsonError>,
+    /// payload of a binary message
+    pub binary: Option<Vec<u8>>,
+    /// connection lifecycle event such as a ping or close, rather than a message
+    pub control: bool,
 }
 
 impl Line {
     pub fn new_json(s: String, d: Direction) -> Self {
-        let mut fmt = JsonFormatter::default();
+        let mut fmt = JsonFormatter;
         match fmt.format(&s) {
-            Ok(s) =>  {
+            Ok(text) =>  {
                 Line {
-                    timestamp: Instant::now(),
+                    timestamp: SystemTime::now(),
                     treat_as_json: true,
-                    text: s,
+                    text,
                     invalid_json: false,
                     dir: d,
+                    conn: None,
+                    raw: Some(s),
+                    json_error: None,
+                    binary: None,
+                    control: false,
                 }
             },
             Err(e) => {
                 eprintln!("message was not valid json. error: {}. json: {}", e, s);
-                let mut line = Line::new_log(s);
+                let mut line = Line::new_log(s.clone());
                 line.invalid_json = true;
+                line.dir = d;
+                line.raw = Some(s);
+                line.json_error = Some(e);
                 line
             },
         }
@@ -43,30 +150,101 @@ impl Line {
 
     pub fn new_log(s: String) -> Self {
         Line {
-            timestamp: Instant::now(),
+            timestamp: SystemTime::now(),
             treat_as_json: false,
             text: s,
             invalid_json: false,
             dir: Direction::Outgoing,
+            conn: None,
+            raw: None,
+            json_error: None,
+            binary: None,
+            control: false,
         }
     }
 
+    pub fn new_binary(bytes: Vec<u8>, d: Direction) -> Self {
+        let mut line = Line::new_log(format!("binary, {} bytes", bytes.len()));
+        line.dir = d;
+        line.binary = Some(bytes);
+        line
+    }
+
+    pub fn new_control(s: String, d: Direction) -> Self {
+        let mut line = Line::new_log(s);
+        line.dir = d;
+        line.control = true;
+        line
+    }
+
+    pub fn with_conn(mut self, id: &ConnId) -> Self {
+        self.conn = Some(id.clone());
+        self
+    }
+
     pub fn format_date(&self) -> String {
-        String::new()
+        time::format_clock(self.timestamp)
     }
 
-    pub fn outgoing(&self) -> bool {
-        match self.dir {
-            Direction::Outgoing => {
-               true 
-            },
-            _ => {
-                false
-            },
+    pub fn to_json(&self) -> JsonValue {
+        let mut members = vec![
+            (String::from("time"), JsonValue::String(time::format_iso8601(self.timestamp))),
+        ];
+        if self.is_message() || self.control {
+            members.push((String::from("dir"), JsonValue::String(String::from(if self.outgoing() { "out" } else { "in" }))));
         }
+        if let Some(ref conn) = self.conn {
+            members.push((String::from("conn"), JsonValue::String(conn.to_string())));
+        }
+        if self.control {
+            members.push((String::from("control"), JsonValue::Bool(true)));
+        }
+        members.push((String::from("json"), JsonValue::Bool(self.treat_as_json)));
+        members.push((String::from("invalid_json"), JsonValue::Bool(self.invalid_json)));
+        if let Some(ref e) = self.json_error {
+            members.push((String::from("json_error"), JsonValue::String(e.to_string())));
+        }
+        members.push((String::from("text"), JsonValue::String(self.text.clone())));
+        if let Some(ref raw) = self.raw {
+            members.push((String::from("raw"), JsonValue::String(raw.clone())));
+        }
+        if let Some(ref bytes) = self.binary {
+            members.push((String::from("binary"), JsonValue::String(binary::base64_encode(bytes))));
+        }
+
+        JsonValue::Object(members)
+    }
+
+    /// Whether the line carries a message payload rather than a log text.
+    pub fn is_message(&self) -> bool {
+        self.treat_as_json || self.invalid_json || self.binary.is_some()
+    }
+
+    pub fn outgoing(&self) -> bool {
+        matches!(self.dir, Direction::Outgoing)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterAction {
+    Drop,
+    Hold,
+}
+
+/// Commands for adapters beyond sending messages.
+#[derive(Debug)]
+pub enum Control {
+    /// drop or hold proxied messages matching the pattern
+    Filter(FilterAction, Regex),
+    ClearFilters,
+    /// forward all held messages
+    Release,
+    /// ping the target, or all connections, and report the round trip time
+    Ping(Option<ConnId>),
+    /// close the target, or all connections, with a status code and reason
+    Close(Option<ConnId>, u16, String),
+}
+
 pub trait Adapter {
 
     fn status(&mut self) -> Result<()> {
@@ -77,7 +255,19 @@ pub trait Adapter {
         None
     }
 
-    fn send_message(&mut self, _: &String) {
+    fn send_message(&mut self, _: &String, _: Option<&ConnId>) {
+    }
+
+    fn send_binary(&mut self, _: &[u8], _: Option<&ConnId>) {
+    }
+
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        vec![]
+    }
+
+    /// Returns whether the adapter supports the command.
+    fn control(&mut self, _: &Control) -> bool {
+        false
     }
 }
 
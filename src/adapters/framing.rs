@@ -0,0 +1,274 @@
+use std::fmt::Display;
+use anyhow::anyhow;
+
+use crate::{json::JsonFormatter, jsonreader::{JsonSplitter, SplitError}};
+
+/// How messages are delimited on a raw TCP stream.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Framing {
+    /// json values written back to back
+    #[default]
+    Json,
+    /// one json value per line
+    Lines,
+    /// each message preceded by its length as 4-byte big-endian integer
+    LengthPrefixed,
+    /// each message terminated by a NUL byte
+    Nul,
+}
+
+impl std::str::FromStr for Framing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Framing::Json),
+            "ndjson" | "lines" => Ok(Framing::Lines),
+            "length" => Ok(Framing::LengthPrefixed),
+            "nul" => Ok(Framing::Nul),
+            _ => Err(anyhow!("unknown framing: {}", s)),
+        }
+    }
+}
+
+impl Display for Framing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Framing::Json => write!(f, "json"),
+            Framing::Lines => write!(f, "ndjson"),
+            Framing::LengthPrefixed => write!(f, "length"),
+            Framing::Nul => write!(f, "nul"),
+        }
+    }
+}
+
+impl Framing {
+    /// The bytes to write for `msg`, including delimiter or prefix.
+    pub fn encode(&self, msg: &[u8]) -> Vec<u8> {
+        match self {
+            Framing::Json => msg.to_vec(),
+            Framing::Lines => {
+                // mocks are usually pretty-printed, but every
+                // message has to stay on a single line
+                let mut r = match std::str::from_utf8(msg) {
+                    Ok(s) => JsonFormatter.format(s)
+                        .unwrap_or_else(|_| s.trim_end().replace(['\r', '\n'], " "))
+                        .into_bytes(),
+                    Err(_) => msg.to_vec(),
+                };
+                r.push(b'\n');
+                r
+            },
+            Framing::LengthPrefixed => {
+                let mut r = (msg.len() as u32).to_be_bytes().to_vec();
+                r.extend_from_slice(msg);
+                r
+            },
+            Framing::Nul => {
+                let mut r = msg.to_vec();
+                r.push(b'\0');
+                r
+            },
+        }
+    }
+}
+
+/// Splits the bytes read from a stream into messages according to its `Framing`.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    framing: Framing,
+    max_size: usize,
+    splitter: JsonSplitter,
+    buffer: Vec<u8>,
+    // frames completed before an error, returned after `reset`
+    ready: Vec<String>,
+    // frames dropped on their own, the stream goes on after them
+    skipped: Vec<SplitError>,
+}
+
+const LENGTH_PREFIX: usize = 4;
+
+impl Decoder {
+    pub fn new(framing: Framing, max_size: usize) -> Self {
+        Decoder {
+            framing,
+            max_size,
+            splitter: JsonSplitter::new(max_size),
+            buffer: vec![],
+            ready: vec![],
+            skipped: vec![],
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<String>, SplitError> {
+        if self.framing == Framing::Json {
+            return self.splitter.push(bytes);
+        }
+
+        self.buffer.extend_from_slice(bytes);
+        match self.framing {
+            Framing::Lines => self.split_at(b'\n')?,
+            Framing::Nul => self.split_at(b'\0')?,
+            _ => self.split_length_prefixed()?,
+        }
+
+        Ok(std::mem::take(&mut self.ready))
+    }
+
+    /// Ends the stream. A last line without newline still counts
+    /// as message, any other leftover is incomplete.
+    pub fn flush(&mut self) -> Result<Vec<String>, SplitError> {
+        if self.framing == Framing::Json {
+            return self.splitter.flush();
+        }
+
+        let rest = std::mem::take(&mut self.buffer);
+        if !rest.iter().all(|c| c.is_ascii_whitespace()) {
+            if self.framing != Framing::Lines {
+                return Err(SplitError::Incomplete);
+            }
+            self.complete(rest);
+        }
+
+        Ok(std::mem::take(&mut self.ready))
+    }
+
+    /// Discards the partial message and recovers from errors. Messages
+    /// completed before the error are returned by the next call.
+    pub fn reset(&mut self) {
+        self.splitter.reset();
+        self.buffer.clear();
+    }
+
+    /// Whether the stream can go on after `e` and `reset`. A length prefix
+    /// that is too large leaves no way to find where the next message starts.
+    pub fn can_recover(&self, e: &SplitError) -> bool {
+        !(self.framing == Framing::LengthPrefixed && matches!(e, SplitError::TooLarge(_)))
+    }
+
+    /// Why frames were dropped since the last call. Unlike errors
+    /// returned by `push`, these did not affect the frames around them.
+    pub fn take_skipped(&mut self) -> Vec<SplitError> {
+        let mut skipped = std::mem::take(&mut self.skipped);
+        skipped.append(&mut self.splitter.take_skipped());
+        skipped
+    }
+
+    fn split_at(&mut self, delimiter: u8) -> Result<(), SplitError> {
+        while let Some(pos) = self.buffer.iter().position(|&c| c == delimiter) {
+            let mut frame: Vec<u8> = self.buffer.drain(..=pos).collect();
+            frame.pop();
+            if frame.last() == Some(&b'\r') {
+                frame.pop();
+            }
+            if frame.iter().all(|c| c.is_ascii_whitespace()) {
+                continue;
+            }
+            self.complete(frame);
+        }
+
+        if self.buffer.len() > self.max_size {
+            return Err(SplitError::TooLarge(self.max_size));
+        }
+
+        Ok(())
+    }
+
+    fn split_length_prefixed(&mut self) -> Result<(), SplitError> {
+        while self.buffer.len() >= LENGTH_PREFIX {
+            let mut prefix = [0u8; LENGTH_PREFIX];
+            prefix.copy_from_slice(&self.buffer[..LENGTH_PREFIX]);
+            let len = u32::from_be_bytes(prefix) as usize;
+
+            if len > self.max_size {
+                return Err(SplitError::TooLarge(self.max_size));
+            }
+            if self.buffer.len() < LENGTH_PREFIX + len {
+                break;
+            }
+
+            let frame = self.buffer.drain(..LENGTH_PREFIX + len).skip(LENGTH_PREFIX).collect();
+            self.complete(frame);
+        }
+
+        Ok(())
+    }
+
+    fn complete(&mut self, frame: Vec<u8>) {
+        match String::from_utf8(frame) {
+            Ok(msg) => self.ready.push(msg),
+            Err(_) => self.skipped.push(SplitError::InvalidUtf8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lines() {
+        let mut d = Decoder::new(Framing::Lines, 100);
+        assert_eq!(d.push(b"{\"a\":1}\r\n\n{\"b\"").unwrap(), vec!["{\"a\":1}"]);
+        assert_eq!(d.push(b":2}\n[3]").unwrap(), vec!["{\"b\":2}"]);
+        assert_eq!(d.flush().unwrap(), vec!["[3]"]);
+    }
+
+    #[test]
+    fn split_nul_terminated() {
+        let mut d = Decoder::new(Framing::Nul, 100);
+        assert_eq!(d.push(b"{}\0[1]\0\"x").unwrap(), vec!["{}", "[1]"]);
+        assert_eq!(d.flush(), Err(SplitError::Incomplete));
+    }
+
+    #[test]
+    fn split_length_prefixed() {
+        let mut d = Decoder::new(Framing::LengthPrefixed, 100);
+        let mut bytes = Framing::LengthPrefixed.encode(b"{\"a\":1}");
+        bytes.extend(Framing::LengthPrefixed.encode(b"[]"));
+
+        let (first, second) = bytes.split_at(2);
+        assert!(d.push(first).unwrap().is_empty());
+        assert_eq!(d.push(second).unwrap(), vec!["{\"a\":1}", "[]"]);
+        assert_eq!(d.push(&[0, 0, 1, 0]), Err(SplitError::TooLarge(100)));
+    }
+
+    #[test]
+    fn skip_only_invalid_frames() {
+        let mut d = Decoder::new(Framing::Lines, 100);
+        assert_eq!(d.push(b"1\n\xff\n3\n4").unwrap(), vec!["1", "3"]);
+        assert_eq!(d.take_skipped(), vec![SplitError::InvalidUtf8]);
+        assert_eq!(d.flush().unwrap(), vec!["4"]);
+
+        let mut d = Decoder::new(Framing::LengthPrefixed, 100);
+        let mut bytes = Framing::LengthPrefixed.encode(b"\xff");
+        bytes.extend(Framing::LengthPrefixed.encode(b"[]"));
+        assert_eq!(d.push(&bytes).unwrap(), vec!["[]"]);
+        assert_eq!(d.take_skipped(), vec![SplitError::InvalidUtf8]);
+    }
+
+    #[test]
+    fn keep_frames_completed_before_error() {
+        let mut d = Decoder::new(Framing::Nul, 4);
+        assert_eq!(d.push(b"1\0[1,2,3]"), Err(SplitError::TooLarge(4)));
+        assert!(d.can_recover(&SplitError::TooLarge(4)));
+        d.reset();
+        assert_eq!(d.push(b"2\0").unwrap(), vec!["1", "2"]);
+        assert!(!Decoder::new(Framing::LengthPrefixed, 4).can_recover(&SplitError::TooLarge(4)));
+    }
+
+    #[test]
+    fn skip_oversized_json_values() {
+        let mut d = Decoder::new(Framing::Json, 8);
+        assert_eq!(d.push(b"{\"a\": \"}}}}}}\"} {\"b\": 1}").unwrap(), vec!["{\"b\": 1}"]);
+        assert_eq!(d.take_skipped(), vec![SplitError::TooLarge(8)]);
+    }
+
+    #[test]
+    fn encode_delimiters() {
+        assert_eq!(Framing::Lines.encode(b"{\n  \"a\": 1\n}\n"), b"{\"a\":1}\n");
+        assert_eq!(Framing::Nul.encode(b"{}"), b"{}\0");
+        assert_eq!(Framing::LengthPrefixed.encode(b"{}"), b"\0\0\0\x02{}");
+        assert_eq!(Framing::Json.encode(b"{}"), b"{}");
+    }
+}
@@ -1,4 +1,7 @@
 pub mod ws;
+pub mod ws_client;
 pub mod test;
 pub mod common;
 pub mod tcp;
+pub mod framing;
+pub mod proxy;
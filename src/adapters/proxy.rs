@@ -0,0 +1,434 @@
+use std::{collections::HashMap, io::ErrorKind, net::{TcpStream, ToSocketAddrs}, sync::mpsc::{Receiver, TryRecvError}, time::Instant};
+use regex::Regex;
+use websocket::{sync::{Client, Stream}, CloseData, OwnedMessage, WebSocketError};
+use super::common::{Adapter, ConnId, Connection, ConnectionInfo, Control, Direction, FilterAction, Line};
+use super::ws::{accept_client, describe_close, format_rtt, HandshakeOptions, WsListener, WsStream};
+use super::ws_client::{check_url, describe_error, dial, DialResult};
+
+const LOG_PREFIX: &str = "proxy:";
+const CLIENT_KIND: &str = "px";
+const UPSTREAM_KIND: &str = "up";
+// messages read per side and poll, so one busy pair cannot stall the others
+const MAX_READS: usize = 64;
+
+enum Upstream {
+    /// messages from the client are queued until the upstream connection is up
+    Dialing(Receiver<DialResult>, Vec<OwnedMessage>),
+    Connected(Box<Connection<Client<TcpStream>>>),
+}
+
+/// An accepted client and its own connection to the upstream server.
+struct ProxyPair {
+    client: Connection<Client<WsStream>>,
+    upstream: Upstream,
+}
+
+impl ProxyPair {
+    fn send(&mut self, towards_upstream: bool, m: OwnedMessage) -> Result<(), String> {
+        if !towards_upstream {
+            return forward(&mut self.client, m);
+        }
+
+        match self.upstream {
+            Upstream::Dialing(_, ref mut queue) => {
+                queue.push(m);
+                Ok(())
+            },
+            Upstream::Connected(ref mut c) => forward(c, m),
+        }
+    }
+
+    fn close(&mut self, data: Option<CloseData>) {
+        let _ = self.client.stream.send_message(&OwnedMessage::Close(data.clone()));
+        if let Upstream::Connected(ref mut c) = self.upstream {
+            let _ = c.stream.send_message(&OwnedMessage::Close(data));
+        }
+    }
+
+    /// The side `target` refers to, `true` for upstream. Without a
+    /// target that is the client, as for injected messages.
+    fn target_side(&self, target: Option<&ConnId>) -> Option<bool> {
+        match self.upstream {
+            Upstream::Connected(ref up) if target.is_some() && up.is_target(target) => Some(true),
+            _ if self.client.is_target(target) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+fn forward<S: Stream>(c: &mut Connection<Client<S>>, m: OwnedMessage) -> Result<(), String> {
+    c.stream.send_message(&m)
+        .map_err(|e| format!("could not forward to {}: {}", c.info, describe_error(e)))?;
+    if matches!(m, OwnedMessage::Text(_) | OwnedMessage::Binary(_)) {
+        c.info.sent += 1;
+    }
+    Ok(())
+}
+
+/// A message held back by a filter until `Control::Release`.
+struct HeldMessage {
+    /// client of the pair the message belongs to
+    pair: ConnId,
+    towards_upstream: bool,
+    message: OwnedMessage,
+}
+
+/// Accepts WebSocket clients and forwards each one to its own connection
+/// to an upstream server. Messages from clients are shown as incoming lines,
+/// messages from the server as outgoing ones, tagged with the client (`px#1`)
+/// or upstream (`up#2`) connection they came from.
+pub struct ProxyAdapter {
+    listener: WsListener,
+    upstream_url: String,
+    pairs: Vec<ProxyPair>,
+    filters: Vec<(FilterAction, Regex)>,
+    held: Vec<HeldMessage>,
+    // pings sent with :ping, by the connection they went to
+    pings: HashMap<ConnId, Instant>,
+    lines: Vec<Line>,
+}
+
+impl ProxyAdapter {
+    pub fn from_addr(addr: impl ToSocketAddrs, upstream_url: &str) -> anyhow::Result<Self> {
+        check_url(upstream_url)?;
+
+        let listener = WsListener::bind(addr, None, HandshakeOptions::default())?;
+        let intro = Line::new_log(format!("proxying {} to {}", listener.local_addr()?, upstream_url));
+
+        Ok(ProxyAdapter {
+            listener,
+            upstream_url: String::from(upstream_url),
+            pairs: vec![],
+            filters: vec![],
+            held: vec![],
+            pings: HashMap::new(),
+            lines: vec![intro],
+        })
+    }
+
+    fn accept_connections(&mut self) {
+        if let Some(c) = accept_client(&mut self.listener, CLIENT_KIND, &mut self.lines) {
+            self.lines.push(Line::new_log(format!("client connected: {}, connecting to {}", c.info, self.upstream_url)).with_conn(&c.info.id));
+            self.pairs.push(ProxyPair {
+                client: c,
+                upstream: Upstream::Dialing(dial(&self.upstream_url), vec![]),
+            });
+        }
+    }
+
+    fn check_pairs(&mut self) {
+        let mut len = self.pairs.len();
+        let mut i = 0;
+
+        while i < len {
+            let pair = self.pairs.get_mut(i).unwrap();
+            match ProxyAdapter::poll_pair(pair, &self.upstream_url, &self.filters, &mut self.held, &mut self.pings, &mut self.lines) {
+                Some(reason) => {
+                    self.close_pair(i, reason);
+                    len -= 1;
+                },
+                None => i += 1,
+            }
+        }
+    }
+
+    /// Connects the upstream side and forwards what both sides sent.
+    /// Returns why the pair has to be closed, if it has to.
+    fn poll_pair(pair: &mut ProxyPair, url: &str, filters: &[(FilterAction, Regex)], held: &mut Vec<HeldMessage>, pings: &mut HashMap<ConnId, Instant>, lines: &mut Vec<Line>) -> Option<String> {
+        if let Upstream::Dialing(ref rx, ref mut queue) = pair.upstream {
+            match rx.try_recv() {
+                Ok(Ok(client)) => {
+                    if let Err(e) = client.set_nonblocking(true) {
+                        return Some(format!("could not connect to {}: {}", url, e));
+                    }
+                    let peer = client.peer_addr().ok();
+                    let mut up = Connection::new(UPSTREAM_KIND, peer, client);
+                    up.info.path = Some(String::from(url));
+                    lines.push(Line::new_log(format!("connected {} to upstream {}", pair.client.info, up.info)).with_conn(&up.info.id));
+
+                    let queue = std::mem::take(queue);
+                    pair.upstream = Upstream::Connected(Box::new(up));
+                    for m in queue {
+                        if let Err(e) = pair.send(true, m) {
+                            return Some(e);
+                        }
+                    }
+                },
+                Ok(Err(e)) => return Some(format!("could not connect to {}: {}", url, e)),
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => return Some(format!("could not connect to {}", url)),
+            }
+        }
+
+        for towards_upstream in [true, false] {
+            let mut reads = 0;
+            while reads < MAX_READS {
+                reads += 1;
+                let (result, from) = if towards_upstream {
+                    (pair.client.stream.recv_message(), &mut pair.client.info)
+                } else {
+                    match pair.upstream {
+                        Upstream::Connected(ref mut c) => (c.stream.recv_message(), &mut c.info),
+                        Upstream::Dialing(..) => break,
+                    }
+                };
+
+                let m = match result {
+                    Ok(m) => m,
+                    Err(WebSocketError::IoError(ref e)) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        eprintln!("{} encountered error: {}", LOG_PREFIX, e);
+                        return Some(format!("lost connection to {}: {}", from, describe_error(e)));
+                    },
+                };
+
+                let from_id = from.id.clone();
+                // what the server sends travels towards the client
+                let dir = if towards_upstream { Direction::Incoming } else { Direction::Outgoing };
+                match m {
+                    OwnedMessage::Text(ref text) => {
+                        from.received += 1;
+                        lines.push(Line::new_json(text.clone(), dir).with_conn(&from_id));
+
+                        let action = filters.iter().find(|(_, r)| r.is_match(text)).map(|(a, _)| *a);
+                        match action {
+                            Some(FilterAction::Drop) => {
+                                lines.push(Line::new_log(format!("dropped message from {}", from_id)).with_conn(&from_id));
+                                continue;
+                            },
+                            Some(FilterAction::Hold) => {
+                                lines.push(Line::new_log(format!("holding message from {}, :release to forward it", from_id)).with_conn(&from_id));
+                                held.push(HeldMessage { pair: pair.client.info.id.clone(), towards_upstream, message: m });
+                                continue;
+                            },
+                            None => {},
+                        }
+                    },
+                    OwnedMessage::Binary(ref data) => {
+                        from.received += 1;
+                        lines.push(Line::new_binary(data.clone(), dir).with_conn(&from_id));
+                    },
+                    OwnedMessage::Close(_) => {
+                        let _ = pair.send(towards_upstream, m);
+                        return Some(format!("{} closed the connection", from_id));
+                    },
+                    OwnedMessage::Pong(_) => {
+                        // answers a ping of :ping, not one of the other side
+                        if let Some(sent) = pings.remove(&from_id) {
+                            lines.push(Line::new_control(format!("pong, round trip {}", format_rtt(sent.elapsed())), Direction::Incoming).with_conn(&from_id));
+                            continue;
+                        }
+                    },
+                    // other control frames are passed on, so both ends see each other's pings
+                    OwnedMessage::Ping(_) => {},
+                }
+
+                if let Err(e) = pair.send(towards_upstream, m) {
+                    return Some(e);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn close_pair(&mut self, i: usize, reason: String) {
+        let pair = self.remove_pair(i, None);
+        self.lines.push(Line::new_log(format!("closed {}: {}", pair.client.info, reason)).with_conn(&pair.client.info.id));
+    }
+
+    /// Closes both ends of a pair, sending `data` in the close frames.
+    fn remove_pair(&mut self, i: usize, data: Option<CloseData>) -> ProxyPair {
+        let mut pair = self.pairs.swap_remove(i);
+        pair.close(data);
+
+        let id = pair.client.info.id.clone();
+        self.held.retain(|h| h.pair != id);
+        self.pings.remove(&id);
+        if let Upstream::Connected(ref up) = pair.upstream {
+            self.pings.remove(&up.info.id);
+        }
+        pair
+    }
+
+    fn release(&mut self) {
+        let held = std::mem::take(&mut self.held);
+        let mut released = 0;
+
+        for h in held {
+            let Some(i) = self.pairs.iter().position(|p| p.client.info.id == h.pair) else {
+                continue;
+            };
+            match self.pairs[i].send(h.towards_upstream, h.message) {
+                Ok(_) => released += 1,
+                Err(e) => self.close_pair(i, e),
+            }
+        }
+
+        self.lines.push(Line::new_log(format!("released {} held messages", released)));
+    }
+}
+
+impl Adapter for ProxyAdapter {
+    fn get_lines(&mut self) -> Option<Vec<Line>> {
+        self.accept_connections();
+        self.check_pairs();
+
+        if !self.lines.is_empty() {
+            Some(std::mem::take(&mut self.lines))
+        } else {
+            None
+        }
+    }
+
+    fn send_message(&mut self, input: &String, target: Option<&ConnId>) {
+        self.inject(OwnedMessage::Text(input.clone()), target);
+    }
+
+    fn send_binary(&mut self, input: &[u8], target: Option<&ConnId>) {
+        self.inject(OwnedMessage::Binary(input.to_vec()), target);
+    }
+
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut r = vec![];
+        for p in self.pairs.iter() {
+            r.push(p.client.info.clone());
+            if let Upstream::Connected(ref up) = p.upstream {
+                r.push(up.info.clone());
+            }
+        }
+        r
+    }
+
+    fn control(&mut self, cmd: &Control) -> bool {
+        match cmd {
+            Control::Ping(target) => self.ping(target.as_ref()),
+            Control::Close(target, code, reason) => self.close(target.as_ref(), *code, reason),
+            _ => return self.control_filters(cmd),
+        }
+        true
+    }
+}
+
+impl ProxyAdapter {
+    /// Broadcasts go to all clients. Targeting an upstream
+    /// connection injects the message towards the server.
+    fn inject(&mut self, message: OwnedMessage, target: Option<&ConnId>) {
+        let mut len = self.pairs.len();
+        let mut i = 0;
+
+        if len == 0 && target.is_none() {
+            self.lines.push(Line::new_log(String::from("no proxy clients connected, message was not delivered")));
+        }
+
+        while i < len {
+            let pair = self.pairs.get_mut(i).unwrap();
+            let Some(towards_upstream) = pair.target_side(target) else {
+                i += 1;
+                continue;
+            };
+
+            match pair.send(towards_upstream, message.clone()) {
+                Ok(_) => {
+                    let info = match pair.upstream {
+                        Upstream::Connected(ref up) if towards_upstream => &up.info,
+                        _ => &pair.client.info,
+                    };
+                    self.lines.push(Line::new_log(format!("injected message into {}", info)).with_conn(&info.id));
+                    i += 1;
+                },
+                Err(e) => {
+                    self.close_pair(i, e);
+                    len -= 1;
+                },
+            }
+        }
+    }
+
+    /// Pings clients, or the upstream connection targeted. The answer
+    /// is shown with the round trip time and not forwarded.
+    fn ping(&mut self, target: Option<&ConnId>) {
+        let mut len = self.pairs.len();
+        let mut i = 0;
+
+        if len == 0 && target.is_none() {
+            self.lines.push(Line::new_log(String::from("no proxy clients connected")));
+        }
+
+        while i < len {
+            let pair = self.pairs.get_mut(i).unwrap();
+            let ping = OwnedMessage::Ping(vec![]);
+            let (result, info) = match (pair.target_side(target), &mut pair.upstream) {
+                (Some(true), Upstream::Connected(up)) => (up.stream.send_message(&ping), &up.info),
+                (Some(false), _) => (pair.client.stream.send_message(&ping), &pair.client.info),
+                _ => {
+                    i += 1;
+                    continue;
+                },
+            };
+
+            match result {
+                Ok(_) => {
+                    self.pings.insert(info.id.clone(), Instant::now());
+                    self.lines.push(Line::new_control(String::from("ping"), Direction::Outgoing).with_conn(&info.id));
+                    i += 1;
+                },
+                Err(e) => {
+                    let reason = format!("could not ping {}: {}", info, describe_error(e));
+                    self.close_pair(i, reason);
+                    len -= 1;
+                },
+            }
+        }
+    }
+
+    /// Closes both ends of the pairs of all clients, or of the targeted
+    /// connection, with the same code and reason.
+    fn close(&mut self, target: Option<&ConnId>, code: u16, reason: &str) {
+        let mut len = self.pairs.len();
+        let mut i = 0;
+
+        if len == 0 && target.is_none() {
+            self.lines.push(Line::new_log(String::from("no proxy clients connected")));
+        }
+
+        while i < len {
+            if self.pairs[i].target_side(target).is_none() {
+                i += 1;
+                continue;
+            }
+
+            let data = Some(CloseData::new(code, String::from(reason)));
+            let pair = self.remove_pair(i, data.clone());
+            len -= 1;
+
+            let mut text = format!("closed {}", pair.client.info);
+            if let Upstream::Connected(ref up) = pair.upstream {
+                text.push_str(&format!(" and {}", up.info));
+            }
+            text.push_str(&format!(" with {}", describe_close(&data)));
+            self.lines.push(Line::new_control(text, Direction::Outgoing).with_conn(&pair.client.info.id));
+        }
+    }
+
+    fn control_filters(&mut self, cmd: &Control) -> bool {
+        match cmd {
+            Control::Filter(action, pattern) => {
+                let verb = match action {
+                    FilterAction::Drop => "dropping",
+                    FilterAction::Hold => "holding",
+                };
+                self.lines.push(Line::new_log(format!("{} messages matching {}", verb, pattern)));
+                self.filters.push((*action, pattern.clone()));
+            },
+            Control::ClearFilters => {
+                self.lines.push(Line::new_log(format!("removed {} filters", self.filters.len())));
+                self.filters.clear();
+            },
+            Control::Release => self.release(),
+            _ => return false,
+        }
+
+        true
+    }
+}
@@ -1,12 +1,26 @@
 use std::{io::{ErrorKind, Read, Write}, net::{TcpListener, ToSocketAddrs}};
 use std::net::TcpStream;
-use super::common::{Adapter, Line};
+use super::framing::{Decoder, Framing};
+use super::common::{Adapter, ConnId, Connection, ConnectionInfo, Direction, Line};
+
+const CONN_KIND: &str = "tcp";
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+// reads per stream and poll, so one busy client cannot stall the others
+const MAX_READS: usize = 64;
+
+/// An accepted socket, and the decoder splitting what is read from it into messages.
+#[derive(Debug)]
+struct TcpPeer {
+    socket: TcpStream,
+    decoder: Decoder,
+}
 
 #[derive(Debug)]
 pub struct TcpAdapter {
     listener: TcpListener,
-    streams: Vec<TcpStream>,
+    streams: Vec<Connection<TcpPeer>>,
     lines: Vec<Line>,
+    framing: Framing,
     buffer: Box<[u8;1024]>,
 }
 
@@ -15,8 +29,8 @@ impl Adapter for TcpAdapter {
         self.update_connections();
         self.check_streams();
 
-        if self.lines.len() > 0 {
-            let v = std::mem::replace(&mut self.lines, vec![]);
+        if !self.lines.is_empty() {
+            let v = std::mem::take(&mut self.lines);
             Some(v)
         } else {
             None
@@ -28,24 +42,44 @@ impl Adapter for TcpAdapter {
         Ok(())
     }
 
-    fn send_message(&mut self, input: &String) {
+    fn send_message(&mut self, input: &String, target: Option<&ConnId>) {
+        self.send_bytes(input.as_bytes(), target);
+    }
+
+    fn send_binary(&mut self, input: &[u8], target: Option<&ConnId>) {
+        self.send_bytes(input, target);
+    }
+
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        self.streams.iter().map(|c| c.info.clone()).collect()
+    }
+}
+
+impl TcpAdapter {
+    fn send_bytes(&mut self, input: &[u8], target: Option<&ConnId>) {
         let mut len = self.streams.len();
         let mut i = 0;
-        let mut lines = vec![];
         eprintln!("trying to write to {} streams", len);
+        let frame = self.framing.encode(input);
 
         while i < len {
-            let mut s = self.streams.get(i).unwrap();
+            let c = self.streams.get_mut(i).unwrap();
+            if !c.is_target(target) {
+                i += 1;
+                continue;
+            }
 
-            match s.write_all(input.as_str().as_bytes()) {
+            match c.stream.socket.write_all(&frame) {
                 Ok(_) => {
-                    lines.push(format!("sent message"));
-                    i = i + 1;
+                    c.info.sent += 1;
+                    self.lines.push(Line::new_log(format!("sent message to {}", c.info)).with_conn(&c.info.id));
+                    i += 1;
                 },
                 Err(e) => {
                     eprintln!("error writing to stream {}", e);
+                    self.lines.push(Line::new_log(format!("could not write to {}, closing stream: {}", c.info, e)).with_conn(&c.info.id));
                     self.streams.remove(i);
-                    len = len - 1;
+                    len -= 1;
                 },
             }
         }
@@ -53,16 +87,17 @@ impl Adapter for TcpAdapter {
 }
 
 impl TcpAdapter {
-    pub fn from_addr(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
+    pub fn from_addr(addr: impl ToSocketAddrs, framing: Framing) -> anyhow::Result<Self> {
         // we need to move this into a thread
         let listener = TcpListener::bind(addr)?;
         listener.set_nonblocking(true)?;
-        let intro = Line::new_log(format!("listening at {}", listener.local_addr().unwrap()));
+        let intro = Line::new_log(format!("listening at {} ({} framing)", listener.local_addr().unwrap(), framing));
 
         Ok(Self {
             buffer: Box::new([0u8;1024]),
             streams: vec![],
             lines: vec![intro],
+            framing,
             listener,
         })
     }
@@ -71,9 +106,11 @@ impl TcpAdapter {
         match self.listener.accept() {
             Ok((stream, addr)) => {
                 eprintln!("accepting connection");
-                self.lines.push(Line::new_log(format!("connected with {}", addr)));
                 stream.set_nonblocking(true).expect("to enable non-blocking");
-                self.streams.push(stream);
+                let peer = TcpPeer { socket: stream, decoder: Decoder::new(self.framing, MAX_MESSAGE_SIZE) };
+                let c = Connection::new(CONN_KIND, Some(addr), peer);
+                self.lines.push(Line::new_log(format!("connected with {}", c.info)).with_conn(&c.info.id));
+                self.streams.push(c);
             },
             Err(e) => {
                 match e.kind() {
@@ -90,38 +127,80 @@ impl TcpAdapter {
         let mut len = self.streams.len();
         let mut i = 0;
         while i < len {
-            let s = self.streams.get_mut(i).unwrap();
-            match s.read(&mut *self.buffer) {
-                Ok(mut bytes) => {
-                    eprintln!("read {} bytes from stream", bytes);
-                    if bytes == 0 {
-                        eprintln!("closing {}-th stream", i);
-                        self.streams.remove(i);
-                        len = len - 1;
-                        continue;
-                    } else {
-                        i = i + 1;
-                    }
-
-                    if self.buffer[bytes] == ('\0' as u8) && bytes > 0 {
-                        eprintln!("byte was {}", self.buffer[bytes-1]);
-                        bytes = bytes - 1;
-                    }
-
-                    self.lines.push(Line::new_log(format!("read {} bytes from stream", bytes)));
-                    let msg = String::from_utf8_lossy(&self.buffer.as_slice()[..bytes]);
-                    self.lines.push(Line::new_log(msg.to_string()));
-                },
-                Err(e) => {
-                    match e.kind() {
-                        ErrorKind::WouldBlock => {},
-                        _ => {
-                            self.lines.push(Line::new_log(format!("could not read from stream: (kind: {}) {}", e.kind(), e)));
+            let c = self.streams.get_mut(i).unwrap();
+            let mut closed = false;
+            let mut reads = 0;
+
+            while reads < MAX_READS {
+                reads += 1;
+                let result = match c.stream.socket.read(&mut *self.buffer) {
+                    Ok(0) => {
+                        closed = true;
+                        c.stream.decoder.flush()
+                    },
+                    Ok(bytes) => {
+                        eprintln!("read {} bytes from stream", bytes);
+                        c.stream.decoder.push(&self.buffer[..bytes])
+                    },
+                    Err(e) => {
+                        match e.kind() {
+                            ErrorKind::WouldBlock => {},
+                            ErrorKind::Interrupted => continue,
+                            _ => {
+                                closed = true;
+                                self.lines.push(Line::new_log(format!("could not read from {}: (kind: {}) {}", c.info, e.kind(), e)).with_conn(&c.info.id));
+                            }
+                        };
+                        break;
+                    },
+                };
+
+                let mut lost_sync = false;
+                let (messages, error) = match result {
+                    Ok(messages) => (messages, None),
+                    Err(e) => {
+                        // drop the partial value and pick up with the next
+                        // one, keeping the values completed before the error
+                        if !c.stream.decoder.can_recover(&e) {
+                            lost_sync = true;
+                            closed = true;
                         }
-                    };
-                },
+                        c.stream.decoder.reset();
+                        (c.stream.decoder.flush().unwrap_or_default(), Some(e))
+                    },
+                };
+
+                for msg in messages {
+                    c.info.received += 1;
+                    self.lines.push(Line::new_json(msg, Direction::Incoming).with_conn(&c.info.id));
+                }
+                for e in c.stream.decoder.take_skipped() {
+                    self.lines.push(Line::new_log(format!("discarding message from {}: {}", c.info, e)).with_conn(&c.info.id));
+                }
+                match error {
+                    Some(e) if lost_sync => {
+                        self.lines.push(Line::new_log(format!("closing connection to {}, the next message cannot be found: {}", c.info, e)).with_conn(&c.info.id));
+                    },
+                    Some(e) => {
+                        self.lines.push(Line::new_log(format!("discarding data from {}: {}", c.info, e)).with_conn(&c.info.id));
+                    },
+                    None => {},
+                }
+
+                if closed {
+                    break;
+                }
+            }
+
+            if closed {
+                eprintln!("closing {}-th stream", i);
+                let c = self.streams.remove(i);
+                self.lines.push(Line::new_log(format!("disconnected from {}", c.info)).with_conn(&c.info.id));
+                len -= 1;
+                continue;
             }
-            i = i + 1
+
+            i += 1
         }
     }
 }
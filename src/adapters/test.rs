@@ -8,7 +8,7 @@ pub struct TestAdapter {
 impl Adapter for TestAdapter {
 
     fn get_lines(&mut self) -> Option<Vec<Line>> {
-        self.iter = self.iter + 1;
+        self.iter += 1;
 
         if self.iter % 5 == 0 {
             Some(vec![Line::new_log(format!("{}", self.iter))])
@@ -1,44 +1,121 @@
-use std::{io::ErrorKind, net::{TcpStream, ToSocketAddrs}};
-use websocket::{server::{sync::Server, NoTlsAcceptor}, sync::Client, WebSocketError};
-use super::common::{Adapter, Direction, Line};
+use std::{collections::HashMap, fmt::Display, io::{ErrorKind, Write}, net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, sync::mpsc::{self, Receiver, TryRecvError}, thread, time::{Duration, Instant}};
+use websocket::{native_tls::TlsAcceptor, server::upgrade::HyperIntoWsError, sync::{server::IntoWs, stream::NetworkStream, Client}, CloseData, OwnedMessage, WebSocketError};
+use crate::tls::Certificate;
+use super::ws_client::describe_error;
+use super::common::{Adapter, ConnId, Connection, ConnectionInfo, Control, Direction, Line};
+
+/// Stream of an accepted client, with or without TLS.
+pub type WsStream = Box<dyn NetworkStream + Send>;
+
+/// Checks and negotiation applied to WebSocket handshakes before accepting them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HandshakeOptions {
+    /// subprotocols the server speaks, in order of preference
+    pub protocols: Vec<String>,
+    /// headers a handshake has to contain, optionally with an exact value
+    pub required_headers: Vec<(String, Option<String>)>,
+}
+
+impl HandshakeOptions {
+    /// The preferred protocol among those the client asked for.
+    pub fn select_protocol(&self, requested: &[String]) -> Option<&String> {
+        self.protocols.iter().find(|p| requested.contains(p))
+    }
+
+    /// Why a handshake with these headers has to be rejected, if it has to.
+    /// `header` looks up a header value by name.
+    pub fn check_headers(&self, header: impl Fn(&str) -> Option<String>) -> Option<String> {
+        for (name, expected) in self.required_headers.iter() {
+            match (header(name), expected) {
+                (None, _) => return Some(format!("missing header {}", name)),
+                (Some(v), Some(e)) if v != *e => return Some(format!("unexpected {} header: {}", name, v)),
+                _ => {},
+            }
+        }
+        None
+    }
+}
+
+/// Outcome of a handshake, with the lines it logged on the way.
+type HandshakeResult = (Vec<Line>, Result<Connection<Client<WsStream>>, HandshakeFailure>);
+
+/// Listens for WebSocket connections, optionally over TLS.
+pub struct WsListener {
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    handshake: HandshakeOptions,
+    // handshakes still running on their own threads
+    pending: Vec<Receiver<HandshakeResult>>,
+}
+
+impl WsListener {
+    pub fn bind(addr: impl ToSocketAddrs, tls: Option<TlsAcceptor>, handshake: HandshakeOptions) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(WsListener { listener, tls, handshake, pending: vec![] })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+}
 
 pub struct WebSocketAdapter {
-    server: Server<NoTlsAcceptor>,
-    streams: Vec<Client<TcpStream>>,
+    listener: WsListener,
+    streams: Vec<Connection<Client<WsStream>>>,
+    // when the last unanswered ping was sent to each connection
+    pings: HashMap<ConnId, Instant>,
+    lines: Vec<Line>,
 }
 
 const LOG_PREFIX: &str = "ws-adapter:";
+const CONN_KIND: &str = "ws";
+const TLS_CONN_KIND: &str = "wss";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+const BAD_REQUEST: &str = "400 Bad Request";
+const FORBIDDEN: &str = "403 Forbidden";
 
 impl WebSocketAdapter {
-    pub fn from_addr(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
+    pub fn from_addr(addr: impl ToSocketAddrs, handshake: &HandshakeOptions) -> anyhow::Result<Self> {
+        WebSocketAdapter::from_listener(WsListener::bind(addr, None, handshake.clone())?)
+    }
 
-        let server = Server::bind(addr)?;
-        server.set_nonblocking(true)?;
+    /// Accepts `wss://` connections only.
+    pub fn from_addr_secure(addr: impl ToSocketAddrs, cert: &Certificate, handshake: &HandshakeOptions) -> anyhow::Result<Self> {
+        let mut a = WebSocketAdapter::from_listener(WsListener::bind(addr, Some(cert.acceptor()?), handshake.clone())?)?;
+        a.lines.push(Line::new_log(format!("certificate fingerprint (SHA-256): {}", cert.fingerprint()?)));
+        Ok(a)
+    }
+
+    fn from_listener(listener: WsListener) -> anyhow::Result<Self> {
+        let scheme = if listener.is_tls() { "wss" } else { "ws" };
+        let intro = Line::new_log(format!("listening at {}://{}", scheme, listener.local_addr()?));
 
         Ok(WebSocketAdapter{
-            server,
+            listener,
             streams: vec![],
+            pings: HashMap::new(),
+            lines: vec![intro],
         })
     }
 
     pub fn accept_connections(&mut self) {
-        match self.server.accept() {
-            Ok(stream) => {
-                match stream.accept() {
-                    Ok(s) => {
-                        s.set_nonblocking(true).unwrap();
-                        self.streams.push(s);
-                    },
-                    Err((_, e)) => {
-                        // todo
-                    },
-                }
-            },
-            Err(e) => {
-                match e {
-                    _ => {},
-                }
-            },
+        let kind = if self.listener.is_tls() { TLS_CONN_KIND } else { CONN_KIND };
+        if let Some(c) = accept_client(&mut self.listener, kind, &mut self.lines) {
+            let mut text = format!("client connected: {}", c.info);
+            if let Some(ref path) = c.info.path {
+                text.push_str(&format!(", path {}", path));
+            }
+            if let Some(ref protocol) = c.info.protocol {
+                text.push_str(&format!(", protocol {}", protocol));
+            }
+            self.lines.push(Line::new_control(text, Direction::Incoming).with_conn(&c.info.id));
+            self.streams.push(c);
         }
     }
 
@@ -51,25 +128,37 @@ impl WebSocketAdapter {
         eprintln!("{} checking connections", LOG_PREFIX);
 
         while i < len {
-            let mut remove_stream = false;
-            let s = self.streams.get_mut(i).unwrap();
-            match s.recv_message() {
+            // why the stream has to be removed, if it has to
+            let mut remove_stream = None;
+            let c = self.streams.get_mut(i).unwrap();
+            match c.stream.recv_message() {
                 Ok(message) => {
                     match message {
                         websocket::OwnedMessage::Text(text) => {
-                            let log_line = Line::new_json(text, Direction::Incoming);
+                            c.info.received += 1;
+                            let log_line = Line::new_json(text, Direction::Incoming)
+                                .with_conn(&c.info.id);
                             lines.push(log_line);
                         },
-                        websocket::OwnedMessage::Binary(_) => {
-                            lines.push(Line::new_log(String::from("received a binary message")));
-                            eprintln!("{} received binary", LOG_PREFIX);
-                        }, websocket::OwnedMessage::Close(_) => { remove_stream = true;
+                        websocket::OwnedMessage::Binary(data) => {
+                            c.info.received += 1;
+                            lines.push(Line::new_binary(data, Direction::Incoming).with_conn(&c.info.id));
                         },
-                        websocket::OwnedMessage::Ping(_) => {
-                            eprintln!("{} received ping", LOG_PREFIX);
+                        websocket::OwnedMessage::Close(data) => {
+                            // echo the status code to complete the closing handshake
+                            let _ = c.stream.send_message(&OwnedMessage::Close(data.clone()));
+                            remove_stream = Some(format!("client closed the connection with {}", describe_close(&data)));
+                        },
+                        websocket::OwnedMessage::Ping(data) => {
+                            let _ = c.stream.send_message(&OwnedMessage::Pong(data));
+                            lines.push(Line::new_control(String::from("ping, answered with pong"), Direction::Incoming).with_conn(&c.info.id));
                         },
                         websocket::OwnedMessage::Pong(_) => {
-                            eprintln!("{} received pong", LOG_PREFIX);
+                            let text = match self.pings.remove(&c.info.id) {
+                                Some(sent) => format!("pong, round trip {}", format_rtt(sent.elapsed())),
+                                None => String::from("unsolicited pong"),
+                            };
+                            lines.push(Line::new_control(text, Direction::Incoming).with_conn(&c.info.id));
                         },
                     }
                 },
@@ -82,21 +171,23 @@ impl WebSocketAdapter {
 
                     if !would_block {
                         eprintln!("{} encountered error: {}", LOG_PREFIX, e);
-                        remove_stream = true;
+                        remove_stream = Some(format!("connection lost: {}", describe_error(e)));
                     }
                 },
             }
 
-            if remove_stream {
+            if let Some(reason) = remove_stream {
                 eprintln!("{} removing stream", LOG_PREFIX);
-                self.streams.swap_remove(i);
-                len = len - 1
+                let c = self.streams.swap_remove(i);
+                self.pings.remove(&c.info.id);
+                lines.push(Line::new_control(format!("client disconnected: {}, {}", c.info, reason), Direction::Incoming).with_conn(&c.info.id));
+                len -= 1
             } else {
-                i = i + 1
+                i += 1
             }
         }
 
-        if lines.len() > 0 {
+        if !lines.is_empty() {
             Some(lines)
         } else {
             None
@@ -105,10 +196,346 @@ impl WebSocketAdapter {
 
 }
 
+/// Describes the status code and reason of a close frame.
+pub fn describe_close(data: &Option<CloseData>) -> String {
+    match data {
+        Some(d) if d.reason.is_empty() => format!("code {}", d.status_code),
+        Some(d) => format!("code {} ({})", d.status_code, d.reason),
+        None => String::from("no status code"),
+    }
+}
+
+pub fn format_rtt(rtt: Duration) -> String {
+    format!("{:.1} ms", rtt.as_secs_f64() * 1000.0)
+}
+
+/// A connection attempt which did not end in an accepted client.
+struct HandshakeFailure {
+    peer: SocketAddr,
+    /// request line, if the request could be parsed
+    request: Option<String>,
+    reason: String,
+    /// status of the HTTP response sent back, if any
+    response: Option<&'static str>,
+}
+
+impl HandshakeFailure {
+    fn new(peer: SocketAddr, reason: impl Display) -> Self {
+        HandshakeFailure { peer, request: None, reason: reason.to_string(), response: None }
+    }
+}
+
+impl Display for HandshakeFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "handshake with {} failed", self.peer)?;
+        if let Some(ref request) = self.request {
+            write!(f, " ({})", request)?;
+        }
+        write!(f, ": {}", self.reason)?;
+        match self.response {
+            Some(status) => write!(f, ", responded with {}", status),
+            None => write!(f, ", no response sent"),
+        }
+    }
+}
+
+/// Accepts a pending connection on `listener` and returns a client whose
+/// handshake has finished, if there is one. Handshakes run on their own
+/// threads, as they block until the request is read. Problems are reported as lines.
+pub fn accept_client(listener: &mut WsListener, kind: &'static str, lines: &mut Vec<Line>) -> Option<Connection<Client<WsStream>>> {
+    match listener.listener.accept() {
+        Ok((tcp, peer)) => {
+            let (tx, rx) = mpsc::channel();
+            let tls = listener.tls.clone();
+            let options = listener.handshake.clone();
+            thread::spawn(move || {
+                let mut handshake_lines = vec![];
+                let r = handshake(tls.as_ref(), &options, tcp, peer, kind, &mut handshake_lines);
+                let _ = tx.send((handshake_lines, r));
+            });
+            listener.pending.push(rx);
+        },
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+        Err(e) => {
+            eprintln!("{} could not accept connection: {}", LOG_PREFIX, e);
+            lines.push(Line::new_log(format!("could not accept connection: {}", e)));
+        },
+    }
+
+    let mut i = 0;
+    while i < listener.pending.len() {
+        let (handshake_lines, r) = match listener.pending[i].try_recv() {
+            Ok(done) => done,
+            Err(TryRecvError::Empty) => {
+                i += 1;
+                continue;
+            },
+            Err(TryRecvError::Disconnected) => {
+                listener.pending.remove(i);
+                continue;
+            },
+        };
+        listener.pending.remove(i);
+        lines.extend(handshake_lines);
+
+        match r {
+            Ok(c) => return Some(c),
+            Err(failure) => {
+                eprintln!("{} {}", LOG_PREFIX, failure);
+                lines.push(Line::new_log(failure.to_string()));
+            },
+        }
+    }
+
+    None
+}
+
+fn handshake(tls: Option<&TlsAcceptor>, options: &HandshakeOptions, tcp: TcpStream, peer: SocketAddr, kind: &'static str, lines: &mut Vec<Line>) -> Result<Connection<Client<WsStream>>, HandshakeFailure> {
+    // the handshake is read in blocking mode, some platforms
+    // pass the non-blocking flag on to accepted sockets. The timeout
+    // ends the thread of a client which never sends its request
+    tcp.set_nonblocking(false)
+        .and_then(|_| tcp.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+        .map_err(|e| HandshakeFailure::new(peer, e))?;
+
+    let stream: WsStream = match tls {
+        Some(acceptor) => {
+            let s = acceptor.accept(tcp)
+                .map_err(|e| HandshakeFailure::new(peer, format!("TLS error: {}", e)))?;
+            Box::new(s)
+        },
+        None => Box::new(tcp),
+    };
+
+    let mut upgrade = match stream.into_ws() {
+        Ok(upgrade) => upgrade,
+        Err((mut stream, request, _, e)) => {
+            let io_error = match e {
+                HyperIntoWsError::Io(ref e) | HyperIntoWsError::Parsing(hyper::Error::Io(ref e)) => Some(e.kind()),
+                _ => None,
+            };
+            let mut failure = match io_error {
+                Some(ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    HandshakeFailure::new(peer, format!("no request within {}s", HANDSHAKE_TIMEOUT.as_secs()))
+                },
+                _ => HandshakeFailure::new(peer, &e),
+            };
+            failure.request = request.map(|r| format!("{} {}", r.subject.0, r.subject.1));
+            // nothing to answer when the client went away or timed out
+            if io_error.is_none() {
+                let body = format!("expected a WebSocket handshake: {}", e);
+                if respond(&mut stream, BAD_REQUEST, &body).is_ok() {
+                    failure.response = Some(BAD_REQUEST);
+                }
+            }
+            return Err(failure);
+        },
+    };
+
+    let path = upgrade.uri();
+    let request = format!("{} {}", upgrade.request.subject.0, path);
+    let headers: Vec<_> = upgrade.request.headers.iter()
+        .map(|h| format!("{}: {}", h.name(), h.value_string()))
+        .collect();
+    lines.push(Line::new_control(format!("handshake from {}: {}, {}", peer, request, headers.join(", ")), Direction::Incoming));
+
+    let rejection = options.check_headers(|name| {
+        upgrade.request.headers.get_raw(name)
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).to_string())
+    });
+    if let Some(reason) = rejection {
+        let mut failure = HandshakeFailure::new(peer, &reason);
+        failure.request = Some(request);
+        if respond(&mut upgrade.stream, FORBIDDEN, &reason).is_ok() {
+            failure.response = Some(FORBIDDEN);
+        }
+        return Err(failure);
+    }
+
+    let protocol = options.select_protocol(upgrade.protocols()).cloned();
+    if let Some(ref p) = protocol {
+        upgrade = upgrade.use_protocol(p.clone());
+    }
+
+    let s = upgrade.accept().map_err(|(_, e)| {
+        let mut failure = HandshakeFailure::new(peer, format!("could not send response: {}", e));
+        failure.request = Some(request);
+        failure
+    })?;
+    s.set_nonblocking(true).map_err(|e| HandshakeFailure::new(peer, e))?;
+
+    let mut c = Connection::new(kind, Some(peer), s);
+    c.info.path = Some(path);
+    c.info.protocol = protocol;
+    Ok(c)
+}
+
+/// Writes a minimal HTTP response, for handshakes which are not accepted.
+fn respond(stream: &mut WsStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!("HTTP/1.1 {}\r\nConnection: close\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+        status, body.len(), body);
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
 impl Adapter for WebSocketAdapter {
     fn get_lines(&mut self) -> Option<Vec<Line>> {
         self.accept_connections();
-        self.check_connections()
+        if let Some(mut lines) = self.check_connections() {
+            self.lines.append(&mut lines);
+        }
+
+        if !self.lines.is_empty() {
+            Some(std::mem::take(&mut self.lines))
+        } else {
+            None
+        }
+    }
+
+    fn send_message(&mut self, input: &String, target: Option<&ConnId>) {
+        self.send_frame(OwnedMessage::Text(input.clone()), target);
+    }
+
+    fn send_binary(&mut self, input: &[u8], target: Option<&ConnId>) {
+        self.send_frame(OwnedMessage::Binary(input.to_vec()), target);
+    }
+
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        self.streams.iter().map(|c| c.info.clone()).collect()
+    }
+
+    fn control(&mut self, cmd: &Control) -> bool {
+        match cmd {
+            Control::Ping(target) => self.ping(target.as_ref()),
+            Control::Close(target, code, reason) => self.close(target.as_ref(), *code, reason),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl WebSocketAdapter {
+    fn ping(&mut self, target: Option<&ConnId>) {
+        let mut len = self.streams.len();
+        let mut i = 0;
+
+        while i < len {
+            let c = self.streams.get_mut(i).unwrap();
+            if !c.is_target(target) {
+                i += 1;
+                continue;
+            }
+
+            match c.stream.send_message(&OwnedMessage::Ping(vec![])) {
+                Ok(_) => {
+                    self.pings.insert(c.info.id.clone(), Instant::now());
+                    self.lines.push(Line::new_control(String::from("ping"), Direction::Outgoing).with_conn(&c.info.id));
+                    i += 1;
+                },
+                Err(e) => {
+                    self.lines.push(Line::new_control(format!("could not ping {}, dropping client: {}", c.info, describe_error(e)), Direction::Outgoing).with_conn(&c.info.id));
+                    self.streams.swap_remove(i);
+                    len -= 1;
+                },
+            }
+        }
+    }
+
+    /// Sends a close frame and drops the connection without waiting for the
+    /// client to answer, the client sees the code and reason either way.
+    fn close(&mut self, target: Option<&ConnId>, code: u16, reason: &str) {
+        let mut len = self.streams.len();
+        let mut i = 0;
+
+        while i < len {
+            if !self.streams[i].is_target(target) {
+                i += 1;
+                continue;
+            }
+
+            let mut c = self.streams.swap_remove(i);
+            len -= 1;
+            self.pings.remove(&c.info.id);
+
+            let data = Some(CloseData::new(code, String::from(reason)));
+            let text = match c.stream.send_message(&OwnedMessage::Close(data.clone())) {
+                Ok(_) => format!("closed {} with {}", c.info, describe_close(&data)),
+                Err(e) => format!("could not close {} cleanly, dropping client: {}", c.info, describe_error(e)),
+            };
+            self.lines.push(Line::new_control(text, Direction::Outgoing).with_conn(&c.info.id));
+        }
+    }
+
+    fn send_frame(&mut self, message: OwnedMessage, target: Option<&ConnId>) {
+        let mut len = self.streams.len();
+        let mut i = 0;
+        eprintln!("{} trying to write to {} clients", LOG_PREFIX, len);
+
+        if len == 0 && target.is_none() {
+            self.lines.push(Line::new_log(String::from("no clients connected, message was not delivered")));
+        }
+
+        while i < len {
+            let c = self.streams.get_mut(i).unwrap();
+            if !c.is_target(target) {
+                i += 1;
+                continue;
+            }
+
+            match c.stream.send_message(&message) {
+                Ok(_) => {
+                    c.info.sent += 1;
+                    self.lines.push(Line::new_log(format!("delivered message to {}", c.info)).with_conn(&c.info.id));
+                    i += 1;
+                },
+                Err(e) => {
+                    eprintln!("{} error writing to client: {}", LOG_PREFIX, e);
+                    self.lines.push(Line::new_log(format!("could not deliver message to {}, dropping client: {}", c.info, e)).with_conn(&c.info.id));
+                    self.streams.swap_remove(i);
+                    len -= 1;
+                },
+            }
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_preferred_protocol() {
+        let opts = HandshakeOptions { protocols: vec![String::from("chat.v2"), String::from("chat")], ..Default::default() };
+        let requested = vec![String::from("chat"), String::from("chat.v2")];
+        assert_eq!(opts.select_protocol(&requested), Some(&String::from("chat.v2")));
+        assert_eq!(opts.select_protocol(&[String::from("mqtt")]), None);
+    }
+
+    #[test]
+    fn reject_missing_or_unexpected_headers() {
+        let opts = HandshakeOptions {
+            required_headers: vec![(String::from("Authorization"), None), (String::from("Origin"), Some(String::from("http://a")))],
+            ..Default::default()
+        };
+        let headers = |origin: &'static str| move |name: &str| match name {
+            "Authorization" => Some(String::from("Bearer x")),
+            "Origin" => Some(String::from(origin)),
+            _ => None,
+        };
+        assert_eq!(opts.check_headers(headers("http://a")), None);
+        assert_eq!(opts.check_headers(headers("http://b")), Some(String::from("unexpected Origin header: http://b")));
+        assert_eq!(opts.check_headers(|_| None), Some(String::from("missing header Authorization")));
+    }
+
+    #[test]
+    fn describe_handshake_failure() {
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut failure = HandshakeFailure::new(peer, "Missing Upgrade WebSocket header");
+        assert_eq!(failure.to_string(), "handshake with 127.0.0.1:5000 failed: Missing Upgrade WebSocket header, no response sent");
+
+        failure.request = Some(String::from("GET /index.html"));
+        failure.response = Some(BAD_REQUEST);
+        assert_eq!(failure.to_string(), "handshake with 127.0.0.1:5000 failed (GET /index.html): Missing Upgrade WebSocket header, responded with 400 Bad Request");
+    }
+}
@@ -0,0 +1,283 @@
+use std::{io::ErrorKind, net::TcpStream, sync::mpsc::{self, Receiver, TryRecvError}, thread, time::{Duration, Instant}};
+use anyhow::anyhow;
+use websocket::{client::ClientBuilder, sync::Client, url::Url, CloseData, OwnedMessage, WebSocketError};
+use super::common::{Adapter, ConnId, Connection, ConnectionInfo, Control, Direction, Line};
+use super::ws::{describe_close, format_rtt};
+
+const LOG_PREFIX: &str = "ws-client:";
+const CONN_KIND: &str = "wsc";
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// messages read per poll, so a chatty server cannot stall the UI
+const MAX_READS: usize = 64;
+
+pub type DialResult = Result<Client<TcpStream>, String>;
+
+enum State {
+    /// waiting until the next connection attempt
+    Idle(Instant),
+    /// a connection attempt is running on a background thread
+    Dialing(Receiver<DialResult>),
+    Connected(Box<Connection<Client<TcpStream>>>),
+}
+
+/// Connects out to a WebSocket server and reconnects with
+/// exponential backoff whenever the connection is lost.
+pub struct WebSocketClientAdapter {
+    url: String,
+    state: State,
+    backoff: Duration,
+    // when the last unanswered ping was sent
+    ping_sent: Option<Instant>,
+    lines: Vec<Line>,
+}
+
+impl WebSocketClientAdapter {
+    pub fn connect(url: &str) -> anyhow::Result<Self> {
+        check_url(url)?;
+
+        Ok(WebSocketClientAdapter {
+            url: String::from(url),
+            state: State::Idle(Instant::now()),
+            backoff: MIN_BACKOFF,
+            ping_sent: None,
+            lines: vec![],
+        })
+    }
+
+    fn retry_later(&mut self, reason: String, conn: Option<ConnId>) {
+        let mut line = Line::new_log(format!("{}, reconnecting in {:.1}s", reason, self.backoff.as_secs_f64()));
+        line.conn = conn;
+        self.lines.push(line);
+        self.state = State::Idle(Instant::now() + self.backoff);
+        self.ping_sent = None;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    fn update_state(&mut self) {
+        match self.state {
+            State::Idle(retry_at) => {
+                if Instant::now() >= retry_at {
+                    eprintln!("{} connecting to {}", LOG_PREFIX, self.url);
+                    self.state = State::Dialing(dial(&self.url));
+                }
+            },
+            State::Dialing(ref rx) => {
+                match rx.try_recv() {
+                    Ok(Ok(client)) => {
+                        if let Err(e) = client.set_nonblocking(true) {
+                            self.retry_later(format!("could not connect to {}: {}", self.url, e), None);
+                            return;
+                        }
+                        let peer = client.peer_addr().ok();
+                        let mut c = Connection::new(CONN_KIND, peer, client);
+                        c.info.path = Some(self.url.clone());
+
+                        self.lines.push(Line::new_control(format!("connected to {} as {}", self.url, c.info), Direction::Outgoing).with_conn(&c.info.id));
+                        self.backoff = MIN_BACKOFF;
+                        self.state = State::Connected(Box::new(c));
+                    },
+                    Ok(Err(e)) => {
+                        self.retry_later(format!("could not connect to {}: {}", self.url, e), None);
+                    },
+                    Err(TryRecvError::Empty) => {},
+                    Err(TryRecvError::Disconnected) => {
+                        self.retry_later(format!("could not connect to {}", self.url), None);
+                    },
+                }
+            },
+            State::Connected(_) => self.check_connection(),
+        }
+    }
+
+    fn check_connection(&mut self) {
+        let State::Connected(ref mut c) = self.state else {
+            return;
+        };
+
+        let mut closed = None;
+        let mut reads = 0;
+        while reads < MAX_READS && closed.is_none() {
+            reads += 1;
+            match c.stream.recv_message() {
+                Ok(message) => {
+                    match message {
+                        OwnedMessage::Text(text) => {
+                            c.info.received += 1;
+                            self.lines.push(Line::new_json(text, Direction::Incoming).with_conn(&c.info.id));
+                        },
+                        OwnedMessage::Binary(data) => {
+                            c.info.received += 1;
+                            self.lines.push(Line::new_binary(data, Direction::Incoming).with_conn(&c.info.id));
+                        },
+                        OwnedMessage::Close(data) => {
+                            let _ = c.stream.send_message(&OwnedMessage::Close(data.clone()));
+                            let text = format!("server closed the connection with {}", describe_close(&data));
+                            self.lines.push(Line::new_control(text, Direction::Incoming).with_conn(&c.info.id));
+                            closed = Some(String::from("server closed the connection"));
+                        },
+                        OwnedMessage::Ping(data) => {
+                            // servers drop clients which do not answer pings
+                            let _ = c.stream.send_message(&OwnedMessage::Pong(data));
+                            self.lines.push(Line::new_control(String::from("ping, answered with pong"), Direction::Incoming).with_conn(&c.info.id));
+                        },
+                        OwnedMessage::Pong(_) => {
+                            let text = match self.ping_sent.take() {
+                                Some(sent) => format!("pong, round trip {}", format_rtt(sent.elapsed())),
+                                None => String::from("unsolicited pong"),
+                            };
+                            self.lines.push(Line::new_control(text, Direction::Incoming).with_conn(&c.info.id));
+                        },
+                    }
+                },
+                Err(WebSocketError::IoError(ref e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("{} encountered error: {}", LOG_PREFIX, e);
+                    closed = Some(format!("lost connection: {}", describe_error(e)));
+                },
+            }
+        }
+
+        if let Some(reason) = closed {
+            let id = c.info.id.clone();
+            let info = c.info.to_string();
+            self.retry_later(format!("disconnected from {}, {}", info, reason), Some(id));
+        }
+    }
+}
+
+pub fn check_url(url: &str) -> anyhow::Result<()> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("invalid url {}: {}", url, e))?;
+    if parsed.scheme() != "ws" {
+        return Err(anyhow!("unsupported url {}, only ws:// is supported", url));
+    }
+    Ok(())
+}
+
+/// Connects to `url` on a background thread, as
+/// connecting blocks until the handshake is done or fails.
+pub fn dial(url: &str) -> Receiver<DialResult> {
+    let (tx, rx) = mpsc::channel();
+    let url = String::from(url);
+
+    thread::spawn(move || {
+        let r = ClientBuilder::new(&url)
+            .map_err(|e| e.to_string())
+            .and_then(|mut b| b.connect_insecure().map_err(describe_error));
+        let _ = tx.send(r);
+    });
+
+    rx
+}
+
+/// The underlying io error says more than its `WebSocketError` wrapper.
+pub fn describe_error(e: WebSocketError) -> String {
+    match e {
+        WebSocketError::IoError(e) => e.to_string(),
+        WebSocketError::NoDataAvailable => String::from("connection closed"),
+        e => e.to_string(),
+    }
+}
+
+impl Adapter for WebSocketClientAdapter {
+    fn get_lines(&mut self) -> Option<Vec<Line>> {
+        self.update_state();
+
+        if !self.lines.is_empty() {
+            Some(std::mem::take(&mut self.lines))
+        } else {
+            None
+        }
+    }
+
+    fn send_message(&mut self, input: &String, target: Option<&ConnId>) {
+        self.send_frame(OwnedMessage::Text(input.clone()), target);
+    }
+
+    fn send_binary(&mut self, input: &[u8], target: Option<&ConnId>) {
+        self.send_frame(OwnedMessage::Binary(input.to_vec()), target);
+    }
+
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        match self.state {
+            State::Connected(ref c) => vec![c.info.clone()],
+            _ => vec![],
+        }
+    }
+
+    fn control(&mut self, cmd: &Control) -> bool {
+        match cmd {
+            Control::Ping(target) => self.ping(target.as_ref()),
+            Control::Close(target, code, reason) => self.close(target.as_ref(), *code, reason),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl WebSocketClientAdapter {
+    fn ping(&mut self, target: Option<&ConnId>) {
+        let State::Connected(ref mut c) = self.state else {
+            return;
+        };
+        if !c.is_target(target) {
+            return;
+        }
+
+        match c.stream.send_message(&OwnedMessage::Ping(vec![])) {
+            Ok(_) => {
+                self.ping_sent = Some(Instant::now());
+                self.lines.push(Line::new_control(String::from("ping"), Direction::Outgoing).with_conn(&c.info.id));
+            },
+            Err(e) => {
+                let id = c.info.id.clone();
+                let info = c.info.to_string();
+                self.retry_later(format!("could not ping {}: {}", info, describe_error(e)), Some(id));
+            },
+        }
+    }
+
+    /// Closes the connection, which is reopened after the usual backoff.
+    fn close(&mut self, target: Option<&ConnId>, code: u16, reason: &str) {
+        let State::Connected(ref mut c) = self.state else {
+            return;
+        };
+        if !c.is_target(target) {
+            return;
+        }
+
+        let data = Some(CloseData::new(code, String::from(reason)));
+        let _ = c.stream.send_message(&OwnedMessage::Close(data.clone()));
+        let _ = c.stream.shutdown();
+
+        let id = c.info.id.clone();
+        let info = c.info.to_string();
+        self.lines.push(Line::new_control(format!("closed {} with {}", info, describe_close(&data)), Direction::Outgoing).with_conn(&id));
+        self.retry_later(format!("disconnected from {}", info), Some(id));
+    }
+
+    fn send_frame(&mut self, message: OwnedMessage, target: Option<&ConnId>) {
+        let State::Connected(ref mut c) = self.state else {
+            if target.is_none() {
+                self.lines.push(Line::new_log(format!("not connected to {}, message was not delivered", self.url)));
+            }
+            return;
+        };
+        if !c.is_target(target) {
+            return;
+        }
+
+        match c.stream.send_message(&message) {
+            Ok(_) => {
+                c.info.sent += 1;
+                self.lines.push(Line::new_log(format!("delivered message to {}", c.info)).with_conn(&c.info.id));
+            },
+            Err(e) => {
+                eprintln!("{} error writing to server: {}", LOG_PREFIX, e);
+                let id = c.info.id.clone();
+                let info = c.info.to_string();
+                self.retry_later(format!("could not deliver message to {}: {}", info, describe_error(e)), Some(id));
+            },
+        }
+    }
+}
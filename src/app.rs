@@ -1,21 +1,87 @@
-use core::time;
-use std::path::Path;
+use core::time::Duration;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::time::Instant;
 use std::{fs, thread};
 
-use crate::parser::{ParseResult, Parser};
-use crate::ui::UI;
-use crate::adapters::common::{Adapter, Direction, Line};
+use crate::parser::{ParseResult, Parser, ProxyCommand, ReplayCommand};
+use crate::filter::LineFilter;
+use crate::replay::{Payload, Replay};
+use crate::json::{JsonFormatter, JsonValue};
+use crate::mocks::{self, MockLibrary};
+use crate::recorder::Recorder;
+use crate::rules::Rules;
+use crate::template::{self, TemplateContext};
+use crate::time;
+use crate::ui::Frontend;
+use crate::adapters::common::{Adapter, ConnId, Control, Direction, FilterAction, Line};
+use regex::Regex;
 
-#[derive(Default)]
 pub struct App
 {
-    ui: UI,
+    ui: Box<dyn Frontend>,
     adapters: Vec<Box::<dyn Adapter>>,
+    mocks: MockLibrary,
+    // request paths with their own mock directories
+    routes: Vec<(String, MockLibrary)>,
+    rules_file: Option<PathBuf>,
+    rules: Rules,
+    pending_replies: Vec<PendingReply>,
+    // last incoming message per connection, for templates
+    last_incoming: HashMap<ConnId, JsonValue>,
+    last_incoming_conn: Option<ConnId>,
+    template_counter: u64,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
     should_exit: bool,
 }
 
+/// A mock scheduled by an auto-responder rule.
+struct PendingReply {
+    due: Instant,
+    target: ConnId,
+    mock: String,
+}
+
 impl App {
 
+    pub fn new(ui: Box<dyn Frontend>, mocks_dir: PathBuf) -> Self {
+        App {
+            ui,
+            adapters: vec![],
+            mocks: MockLibrary::new(mocks_dir),
+            routes: vec![],
+            rules_file: None,
+            rules: Rules::default(),
+            pending_replies: vec![],
+            last_incoming: HashMap::new(),
+            last_incoming_conn: None,
+            template_counter: 0,
+            recorder: None,
+            replay: None,
+            should_exit: false,
+        }
+    }
+
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn set_routes(&mut self, routes: Vec<(String, PathBuf)>) {
+        for (path, dir) in routes.iter() {
+            self.add_line(Line::new_log(format!("mocks for {} are taken from {}", path, dir.display())));
+        }
+        self.routes = routes.into_iter()
+            .map(|(path, dir)| (path, MockLibrary::new(dir)))
+            .collect();
+    }
+
+    pub fn set_rules_file(&mut self, path: PathBuf) {
+        self.rules_file = Some(path);
+        self.load_rules();
+    }
+
     pub fn add(&mut self, adapter: Box<dyn Adapter>) {
         self.adapters.push(adapter);
     }
@@ -24,7 +90,10 @@ impl App {
         self.ui.setup();
 
         loop {
+            self.poll_mocks();
             self.poll_adapters();
+            self.send_pending_replies();
+            self.tick_replay();
             self.poll_keyboard();
             if self.should_exit {
                 break
@@ -32,12 +101,30 @@ impl App {
 
             self.ui.render();
 
-            thread::sleep(time::Duration::from_millis(30));
+            thread::sleep(Duration::from_millis(30));
         }
 
         self.ui.teardown();
     }
 
+    fn add_line(&mut self, line: Line) {
+        if let Some(ref mut r) = self.recorder {
+            if let Err(e) = r.record(&line) {
+                // stop recording instead of failing on every line
+                self.recorder = None;
+                self.ui.add_line(Line::new_log(format!("recording stopped, could not write: {}", e)));
+            }
+        }
+
+        self.ui.add_line(line);
+    }
+
+    fn add_lines(&mut self, lines: Vec<Line>) {
+        for l in lines {
+            self.add_line(l);
+        }
+    }
+
     fn poll_adapters(&mut self) {
         let mut len = self.adapters.len();
         let mut i = 0;
@@ -48,16 +135,21 @@ impl App {
 
             if let Err(e) = a.status() {
                 // remove with bad status
-                self.ui.add_error(e);
+                self.add_line(Line::new_log(format!("{}", e)));
                 self.adapters.swap_remove(i);
-                len = len - 1;
+                len -= 1;
             } else {
 
                 if let Some(lines) = a.get_lines() {
-                    self.ui.add_lines(lines);
+                    for l in lines {
+                        self.remember_incoming(&l);
+                        let matched = self.apply_rules(&l);
+                        self.add_line(l);
+                        self.add_lines(matched);
+                    }
                 }
 
-                i = i + 1;
+                i += 1;
             }
 
         }
@@ -76,9 +168,34 @@ impl App {
                     eprintln!("listing items");
                     self.list_items();
                 },
-                ParseResult::Send(list) => {
+                ParseResult::Clients => {
+                    self.list_clients();
+                },
+                ParseResult::Replay(cmd) => {
+                    self.control_replay(cmd);
+                },
+                ParseResult::Proxy(cmd) => {
+                    self.control_proxy(cmd);
+                },
+                ParseResult::Filter(spec) => {
+                    self.set_filter(&spec);
+                },
+                ParseResult::Ping(target) => {
+                    self.control_connection(target, Control::Ping);
+                },
+                ParseResult::Close(target, code, reason) => {
+                    self.control_connection(target, |t| Control::Close(t, code, reason));
+                },
+                ParseResult::Rules => {
+                    self.load_rules();
+                    self.list_rules();
+                },
+                ParseResult::Send(target, list) => {
                     eprintln!("sending items: {}", list);
-                    self.send_message(list);
+                    self.send_message(target.as_deref().map(ConnId::from), list);
+                },
+                ParseResult::Edit(target, file, save_as) => {
+                    self.edit_message(target.as_deref().map(ConnId::from), file, save_as);
                 },
                 ParseResult::Malformed(s) => {
                     eprintln!("malformed command: {}", s);
@@ -87,39 +204,472 @@ impl App {
         }
     }
 
+    /// Picks up mocks changed on disk.
+    fn poll_mocks(&mut self) {
+        let mut report = self.mocks.poll();
+        for (_, library) in self.routes.iter_mut() {
+            report.extend(library.poll());
+        }
+        self.report_mock_changes(report);
+    }
+
+    fn report_mock_changes(&mut self, report: Vec<String>) {
+        if report.is_empty() {
+            return;
+        }
+        for r in report {
+            self.add_line(Line::new_log(r));
+        }
+
+        // names for completion
+        let mut names: Vec<String> = self.mocks.mocks.keys()
+            .chain(self.routes.iter().flat_map(|(_, l)| l.mocks.keys()))
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        self.ui.set_mocks(names);
+    }
+
     fn list_items(&mut self) {
-        let p = Path::new("./mocks");
-        match fs::read_dir(p) {
+        let mut rows = vec![];
+        for (route, library) in std::iter::once((None, &self.mocks))
+            .chain(self.routes.iter().map(|(r, l)| (Some(r), l)))
+        {
+            let title = match route {
+                Some(r) => format!("mocks for {} in {}", r, library.root.display()),
+                None => format!("mocks in {}", library.root.display()),
+            };
+            if library.mocks.is_empty() {
+                rows.push(format!("no {}", title));
+                continue;
+            }
+            rows.push(format!("{}:", title));
+            rows.extend(library.listing().into_iter().map(|r| format!("  {}", r)));
+        }
+
+        for r in rows {
+            self.add_line(Line::new_log(r));
+        }
+    }
+
+    fn load_rules(&mut self) {
+        let Some(path) = self.rules_file.clone() else {
+            return;
+        };
+
+        match Rules::load(&path) {
+            Ok(rules) => {
+                self.add_line(Line::new_log(format!("loaded {} rules from {}", rules.rules.len(), path.display())));
+                self.rules = rules;
+            },
             Err(e) => {
-                eprintln!("could not list files: {}", e);
-                self.ui.add_line(Line::new_log(String::from("cannot list files. failed to read directory.")));
+                self.add_line(Line::new_log(format!("could not load rules from {}: {}", path.display(), e)));
             },
-            Ok(dir) => {
-                for p in dir {
-                    match  p {
-                        Ok(f) => self.ui.add_line(Line::new_log(f.file_name().to_string_lossy().to_string())),
-                        Err(e) => eprintln!("could not read file: {}", e),
-                    }
+        }
+    }
+
+    fn list_rules(&mut self) {
+        if self.rules.rules.is_empty() {
+            self.add_line(Line::new_log(String::from("no rules loaded")));
+        }
+
+        let lines: Vec<_> = self.rules.rules.iter()
+            .map(|r| Line::new_log(r.to_string()))
+            .collect();
+        self.add_lines(lines);
+    }
+
+    fn remember_incoming(&mut self, line: &Line) {
+        if line.outgoing() || !line.treat_as_json {
+            return;
+        }
+        let Some(ref conn) = line.conn else {
+            return;
+        };
+
+        if let Ok(v) = JsonValue::parse(&line.text) {
+            self.last_incoming.insert(conn.clone(), v);
+            self.last_incoming_conn = Some(conn.clone());
+        }
+    }
+
+    /// Expands template placeholders of a mock. `{{request.*}}` refers to the
+    /// last message of the target, or of the most recent sender when broadcasting.
+    fn expand_template(&mut self, content: &str, target: Option<&ConnId>) -> anyhow::Result<String> {
+        self.template_counter += 1;
+
+        let conn = target.or(self.last_incoming_conn.as_ref());
+        let ctx = TemplateContext {
+            counter: self.template_counter,
+            request: conn.and_then(|c| self.last_incoming.get(c)),
+        };
+
+        template::expand(content, &ctx)
+    }
+
+    /// Schedules the replies of all rules matching `line`.
+    /// Returns log lines describing the matches.
+    fn apply_rules(&mut self, line: &Line) -> Vec<Line> {
+        let mut lines = vec![];
+        if line.outgoing() || !line.is_message() || line.binary.is_some() {
+            return lines;
+        }
+        let Some(ref conn) = line.conn else {
+            return lines;
+        };
+
+        for r in self.rules.matching(&line.text) {
+            lines.push(Line::new_log(format!("rule matched: {}", r)).with_conn(conn));
+            self.pending_replies.push(PendingReply {
+                due: Instant::now() + r.delay,
+                target: conn.clone(),
+                mock: r.mock.clone(),
+            });
+        }
+
+        lines
+    }
+
+    fn send_pending_replies(&mut self) {
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.pending_replies)
+            .into_iter()
+            .partition(|r| r.due <= now);
+        self.pending_replies = pending;
+
+        for r in due {
+            self.send_message(Some(r.target), r.mock);
+        }
+    }
+
+    fn list_clients(&mut self) {
+        let clients: Vec<_> = self.adapters.iter()
+            .flat_map(|a| a.connections())
+            .collect();
+
+        if clients.is_empty() {
+            self.add_line(Line::new_log(String::from("no clients connected")));
+            return;
+        }
+
+        for c in clients {
+            let peer = c.peer
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| String::from("-"));
+
+            let mut text = format!("{:<4} {:<22} since {}  in: {}  out: {}",
+                c.kind, peer, time::format_clock(c.connected_at), c.received, c.sent);
+            if let Some(path) = c.path {
+                text.push_str(&format!("  path: {}", path));
+            }
+            if let Some(protocol) = c.protocol {
+                text.push_str(&format!("  protocol: {}", protocol));
+            }
+
+            self.add_line(Line::new_log(text).with_conn(&c.id));
+        }
+    }
+
+    /// The mock library of the route matching the path the target
+    /// connected at. Broadcasts always use the default library.
+    fn library_for(&mut self, target: Option<&ConnId>) -> &mut MockLibrary {
+        let path = target.and_then(|id| {
+            self.adapters.iter()
+                .flat_map(|a| a.connections())
+                .find(|c| c.id == *id)
+                .and_then(|c| c.path)
+        });
+        let Some(path) = path else {
+            return &mut self.mocks;
+        };
+        let path = path.split('?').next().unwrap_or("");
+
+        // the longest matching route wins, so /ws/chat/admin can override /ws/chat
+        self.routes.iter_mut()
+            .filter(|(r, _)| {
+                let r = r.trim_end_matches('/');
+                path == r || path.starts_with(&format!("{}/", r))
+            })
+            .max_by_key(|(r, _)| r.len())
+            .map(|(_, library)| library)
+            .unwrap_or(&mut self.mocks)
+    }
+
+    /// Looks up a mock, scanning its library first if it is not known
+    /// yet, e.g. because it was created since the last scan.
+    fn find_mock(&mut self, target: Option<&ConnId>, name: &str) -> Result<Payload, String> {
+        let library = self.library_for(target);
+        let mut report = vec![];
+        if library.get(name).is_none() {
+            report = library.scan();
+        }
+
+        let r = match library.get(name) {
+            Some(mock) => mock.payload.clone()
+                .ok_or_else(|| format!("could not send {}: {}", name, mock.error.as_deref().unwrap_or("not loaded"))),
+            None => Err(format!("no such mock: {}, see :ls", name)),
+        };
+        self.report_mock_changes(report);
+        r
+    }
+
+    fn has_connection(&self, id: &ConnId) -> bool {
+        self.adapters.iter()
+            .any(|a| a.connections().iter().any(|c| c.id == *id))
+    }
+
+    fn control_proxy(&mut self, cmd: ProxyCommand) {
+        let filter = |action, p: String| {
+            Regex::new(&p)
+                .map(|r| Control::Filter(action, r))
+                .map_err(|e| format!("invalid pattern {}: {}", p, e))
+        };
+        let cmd = match cmd {
+            ProxyCommand::Drop(p) => filter(FilterAction::Drop, p),
+            ProxyCommand::Hold(p) => filter(FilterAction::Hold, p),
+            ProxyCommand::Release => Ok(Control::Release),
+            ProxyCommand::Clear => Ok(Control::ClearFilters),
+        };
+        let cmd = match cmd {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                self.add_line(Line::new_log(e));
+                return;
+            },
+        };
+
+        let mut handled = false;
+        for a in self.adapters.iter_mut() {
+            handled |= a.control(&cmd);
+        }
+        if !handled {
+            self.add_line(Line::new_log(String::from("no proxy running, see --proxy")));
+        }
+    }
+
+    fn set_filter(&mut self, spec: &str) {
+        if spec.is_empty() {
+            self.ui.set_filter(None);
+            self.add_line(Line::new_log(String::from("filter removed, showing all lines")));
+            return;
+        }
+
+        match LineFilter::parse(spec) {
+            Ok(f) => {
+                self.add_line(Line::new_log(format!("showing only lines matching {}", f)));
+                self.ui.set_filter(Some(f));
+            },
+            Err(e) => self.add_line(Line::new_log(format!("invalid filter: {}", e))),
+        }
+    }
+
+    /// Sends a ping or close to the target, or to all connections supporting it.
+    fn control_connection(&mut self, target: Option<String>, cmd: impl FnOnce(Option<ConnId>) -> Control) {
+        let target = target.as_deref().map(ConnId::from);
+        if let Some(ref id) = target {
+            if !self.has_connection(id) {
+                self.add_line(Line::new_log(format!("no such client: {}", id)));
+                return;
+            }
+        }
+
+        let cmd = cmd(target);
+        let mut handled = false;
+        for a in self.adapters.iter_mut() {
+            handled |= a.control(&cmd);
+        }
+        if !handled {
+            self.add_line(Line::new_log(String::from("no WebSocket connections, see --ws and --connect")));
+        }
+    }
+
+    fn control_replay(&mut self, cmd: ReplayCommand) {
+        if let ReplayCommand::Start(file, speed) = cmd {
+            match Replay::load(Path::new(&file), speed) {
+                Ok(replay) => {
+                    self.add_line(Line::new_log(format!("replaying {} messages from {} at {}x", replay.total(), file, speed)));
+                    self.replay = Some(replay);
+                },
+                Err(e) => {
+                    self.add_line(Line::new_log(format!("could not load replay {}: {}", file, e)));
+                },
+            }
+            return;
+        }
+
+        let Some(ref mut replay) = self.replay else {
+            self.add_line(Line::new_log(String::from("no replay running")));
+            return;
+        };
+
+        match cmd {
+            ReplayCommand::Start(..) => {},
+            ReplayCommand::Pause => {
+                replay.pause();
+                let text = format!("replay paused after {}/{} messages", replay.sent(), replay.total());
+                self.add_line(Line::new_log(text));
+            },
+            ReplayCommand::Resume => {
+                replay.resume();
+                self.add_line(Line::new_log(String::from("replay resumed")));
+            },
+            ReplayCommand::Step => {
+                if let Some(m) = replay.step() {
+                    self.send_payload(m.payload);
                 }
+                self.finish_replay();
+            },
+            ReplayCommand::Stop => {
+                self.replay = None;
+                self.add_line(Line::new_log(String::from("replay stopped")));
             },
         }
     }
 
-    fn send_message(&mut self, file_name: String) {
-        let file_name = String::from("mocks/") + file_name.as_str();
+    fn tick_replay(&mut self) {
+        let Some(ref mut replay) = self.replay else {
+            return;
+        };
 
-        match fs::read_to_string(&file_name) {
-            Ok(content) => {
-                self.ui.add_line(Line::new_json(content.clone(), Direction::Outgoing));
+        for m in replay.due(Instant::now()) {
+            self.send_payload(m.payload);
+        }
+        self.finish_replay();
+    }
 
-                for a in self.adapters.iter_mut() {
-                    a.send_message(&content);
+    fn finish_replay(&mut self) {
+        if self.replay.as_ref().is_some_and(|r| r.is_finished()) {
+            self.replay = None;
+            self.add_line(Line::new_log(String::from("replay finished")));
+        }
+    }
+
+    fn send_message(&mut self, target: Option<ConnId>, file_name: String) {
+        if let Some(ref id) = target {
+            if !self.has_connection(id) {
+                self.add_line(Line::new_log(format!("no such client: {}", id)));
+                return;
+            }
+        }
+
+        match self.find_mock(target.as_ref(), &file_name) {
+            Ok(Payload::Binary(bytes)) => self.send_binary(target, bytes),
+            Ok(Payload::Text(text)) => match self.expand_template(&text, target.as_ref()) {
+                Ok(content) => self.send_content(target, content),
+                Err(e) => self.add_line(Line::new_log(format!("could not send {}: {}", file_name, e))),
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                self.add_line(Line::new_log(e));
+            },
+        }
+    }
+
+    /// Lets the user compose a message in their editor, starting from a mock
+    /// or the last received message, then sends and optionally saves it.
+    fn edit_message(&mut self, target: Option<ConnId>, file: Option<String>, save_as: Option<String>) {
+        if let Some(ref id) = target {
+            if !self.has_connection(id) {
+                self.add_line(Line::new_log(format!("no such client: {}", id)));
+                return;
+            }
+        }
+
+        let dir = self.library_for(target.as_ref()).root.clone();
+        // checked before editing, so the edit is not lost to a bad name
+        let save_path = match save_as.map(|name| mocks::new_mock_path(&dir, &name)) {
+            Some(Ok(path)) => Some(path),
+            Some(Err(e)) => {
+                self.add_line(Line::new_log(format!("could not save message: {}", e)));
+                return;
+            },
+            None => None,
+        };
+
+        let initial = match file {
+            Some(ref f) => match fs::read_to_string(dir.join(f)) {
+                Ok(c) => c,
+                Err(e) => {
+                    self.add_line(Line::new_log(format!("could not open {}: {}", dir.join(f).display(), e)));
+                    return;
+                },
+            },
+            None => {
+                let conn = target.as_ref().or(self.last_incoming_conn.as_ref());
+                match conn.and_then(|c| self.last_incoming.get(c)) {
+                    Some(v) => format!("{}\n", JsonFormatter.pretty(&v.to_string())),
+                    None => String::from("{}\n"),
                 }
             },
+        };
+
+        let content = match self.ui.edit(&initial) {
+            Ok(c) => c,
             Err(e) => {
-                self.ui.add_line(Line::new_log(format!("could not read file: {}", file_name)));
-                eprintln!("could not read file {}: {}", file_name, e);
+                self.add_line(Line::new_log(format!("could not edit message: {}", e)));
+                return;
             },
+        };
+        // a description header is saved, but not sent
+        let (_, body) = mocks::split_header(&content);
+        if body.trim().is_empty() {
+            self.add_line(Line::new_log(String::from("message is empty, nothing was sent")));
+            return;
+        }
+
+        if let Some(path) = save_path {
+            // the library picks the new mock up with its next scan
+            match App::save_mock(&dir, &path, &content) {
+                Ok(_) => self.add_line(Line::new_log(format!("saved message as {}", path.display()))),
+                Err(e) => self.add_line(Line::new_log(format!("could not save {}: {}", path.display(), e))),
+            }
+        }
+
+        match self.expand_template(body, target.as_ref()) {
+            Ok(content) => self.send_content(target, content),
+            Err(e) => self.add_line(Line::new_log(format!("could not send edited message: {}", e))),
+        }
+    }
+
+    /// Writes a new mock file, refusing to follow symlinks out of `root`
+    /// or to replace a file created since `path` was checked.
+    fn save_mock(root: &Path, path: &Path, content: &str) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+            if !fs::canonicalize(parent)?.starts_with(fs::canonicalize(root)?) {
+                return Err(std::io::Error::other(format!("{} is outside of {}", parent.display(), root.display())));
+            }
+        }
+
+        let mut f = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+        f.write_all(content.as_bytes())
+    }
+
+    fn send_content(&mut self, target: Option<ConnId>, content: String) {
+        let mut line = Line::new_json(content.clone(), Direction::Outgoing);
+        line.conn = target.clone();
+        self.add_line(line);
+
+        for a in self.adapters.iter_mut() {
+            a.send_message(&content, target.as_ref());
+        }
+    }
+
+    fn send_binary(&mut self, target: Option<ConnId>, bytes: Vec<u8>) {
+        let mut line = Line::new_binary(bytes.clone(), Direction::Outgoing);
+        line.conn = target.clone();
+        self.add_line(line);
+
+        for a in self.adapters.iter_mut() {
+            a.send_binary(&bytes, target.as_ref());
+        }
+    }
+
+    fn send_payload(&mut self, payload: Payload) {
+        match payload {
+            Payload::Text(text) => self.send_content(None, text),
+            Payload::Binary(bytes) => self.send_binary(None, bytes),
         }
     }
 }
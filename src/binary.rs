@@ -0,0 +1,191 @@
+use anyhow::anyhow;
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_PAD: u8 = b'=';
+const DUMP_WIDTH: usize = 16;
+// length of the base64 rows in the expanded view
+const BASE64_ROW: usize = 64;
+
+/// How binary payloads are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BinaryView {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl BinaryView {
+    pub fn toggle(self) -> Self {
+        match self {
+            BinaryView::Hex => BinaryView::Base64,
+            BinaryView::Base64 => BinaryView::Hex,
+        }
+    }
+
+    /// The first `max` bytes on a single row.
+    pub fn preview(self, bytes: &[u8], max: usize) -> String {
+        let shown = &bytes[..bytes.len().min(max)];
+        let mut r = match self {
+            BinaryView::Hex => to_hex(shown),
+            BinaryView::Base64 => base64_encode(shown),
+        };
+        if shown.len() < bytes.len() {
+            r.push_str(" ...");
+        }
+        r
+    }
+
+    /// All bytes, over as many rows as needed.
+    pub fn rows(self, bytes: &[u8]) -> Vec<String> {
+        match self {
+            BinaryView::Hex => hex_dump(bytes),
+            BinaryView::Base64 => {
+                let encoded = base64_encode(bytes);
+                encoded.as_bytes()
+                    .chunks(BASE64_ROW)
+                    .map(|c| String::from_utf8_lossy(c).to_string())
+                    .collect()
+            },
+        }
+    }
+}
+
+/// Bytes as space separated hex, e.g. `82 a4 74`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let hex: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.join(" ")
+}
+
+/// Classic hex dump with offset, 16 bytes per row and printable ASCII:
+///
+/// `00000000  82 a4 74 79 70 65 a4 70  69 6e 67  |..type.ping|`
+pub fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    let mut rows = vec![];
+
+    for (i, chunk) in bytes.chunks(DUMP_WIDTH).enumerate() {
+        let mut hex = String::new();
+        for (j, b) in chunk.iter().enumerate() {
+            if j == DUMP_WIDTH / 2 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x} ", b));
+        }
+
+        let ascii: String = chunk.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        rows.push(format!("{:08x}  {:<49} |{}|", i * DUMP_WIDTH, hex, ascii));
+    }
+
+    rows
+}
+
+/// Parses hex text such as `82 a4 74` or `82a474`. Whitespace is ignored,
+/// and everything after `#` on a line is a comment.
+pub fn parse_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let digits: Vec<u8> = text.lines()
+        .map(|l| l.split('#').next().unwrap_or(""))
+        .flat_map(|l| l.bytes())
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+
+    if !digits.len().is_multiple_of(2) {
+        return Err(anyhow!("odd number of hex digits"));
+    }
+
+    let mut r = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        let invalid = || anyhow!("invalid hex digits: {}", String::from_utf8_lossy(pair));
+        // from_str_radix would accept a sign, as in +f
+        if !pair.iter().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let s = std::str::from_utf8(pair).unwrap_or("");
+        let b = u8::from_str_radix(s, 16).map_err(|_| invalid())?;
+        r.push(b);
+    }
+
+    Ok(r)
+}
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut r = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                r.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                r.push(BASE64_PAD as char);
+            }
+        }
+    }
+
+    r
+}
+
+pub fn base64_decode(text: &str) -> anyhow::Result<Vec<u8>> {
+    let text = text.trim_end_matches(BASE64_PAD as char);
+    let mut r = Vec::with_capacity(text.len() * 3 / 4);
+    let mut n: u32 = 0;
+    let mut bits = 0;
+
+    for c in text.bytes() {
+        let v = BASE64_CHARS.iter().position(|&b| b == c)
+            .ok_or_else(|| anyhow!("invalid base64 character: {}", c as char))?;
+        n = n << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            r.push((n >> bits) as u8);
+        }
+    }
+
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_round_trip() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+        assert_eq!(base64_encode(&[0xff, 0x00, 0x80]), "/wCA");
+
+        for s in ["", "f", "fo", "foo", "foob", "fooba", "foobar"] {
+            assert_eq!(base64_decode(&base64_encode(s.as_bytes())).unwrap(), s.as_bytes());
+        }
+        assert!(base64_decode("a*b=").is_err());
+    }
+
+    #[test]
+    fn parse_hex_text() {
+        assert_eq!(parse_hex("82 a4\n74 # type\n79").unwrap(), vec![0x82, 0xa4, 0x74, 0x79]);
+        assert_eq!(parse_hex("DEADbeef").unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+        assert!(parse_hex("+f").is_err());
+    }
+
+    #[test]
+    fn dump_rows() {
+        let rows = hex_dump(b"0123456789abcdef\x00x");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|");
+        assert_eq!(rows[1], "00000010  00 78                                             |.x|");
+    }
+
+    #[test]
+    fn preview_is_truncated() {
+        assert_eq!(BinaryView::Hex.preview(&[1, 2, 3], 2), "01 02 ...");
+        assert_eq!(BinaryView::Base64.preview(b"foo", 8), "Zm9v");
+    }
+}
@@ -0,0 +1,280 @@
+use std::path::PathBuf;
+use anyhow::anyhow;
+
+use crate::{adapters::{framing::Framing, ws::HandshakeOptions}, headless::OutputFormat};
+
+pub const USAGE_TEXT: &str = r"
+Usage: termws [options]
+
+Options:
+
+--ws <addr>          - Listen for WebSocket connections at <addr>. May be repeated
+--wss <addr>         - Listen for secure WebSocket connections (wss://) at <addr>.
+                       Needs --cert and --key, or --self-signed. May be repeated
+--cert <file>        - PEM certificate chain for --wss
+--key <file>         - PEM private key for --wss
+--self-signed        - Generate a certificate for localhost. Together with --cert and
+                       --key it is saved there, and reused as long as the files exist
+--protocol <name>    - Subprotocol spoken by --ws and --wss listeners. May be repeated, the
+                       first one requested by a client is selected
+--require-header <name>[=<value>]
+                     - Reject handshakes on --ws and --wss listeners without this header,
+                       or with a different value, e.g. Origin=http://localhost:3000.
+                       May be repeated
+--connect <url>      - Connect to the WebSocket server at <url>, e.g. ws://localhost:9000/feed.
+                       Reconnects when the connection is lost. May be repeated
+--proxy <addr>=<url> - Listen for WebSocket connections at <addr> and forward each one
+                       to the server at <url>, e.g. 127.0.0.1:8081=ws://localhost:9000.
+                       May be repeated
+--tcp <addr>         - Listen for raw TCP connections at <addr>. May be repeated
+--framing <mode>     - Framing of the --tcp adapters following it: json (default),
+                       ndjson, length (4-byte big-endian prefix) or nul
+--mocks <dir>        - Directory containing the json messages, which may be grouped
+                       in subfolders (default: ./mocks)
+--route <path>=<dir> - Take mocks for clients connected at <path> or below from <dir>,
+                       e.g. /ws/chat=./mocks/chat. May be repeated
+--rules <file>       - Auto-responder rules (default: rules.json next to the mocks directory)
+--record <file>      - Append every line to <file> as JSON Lines
+--test-adapter       - Add an adapter emitting a counter, for testing the UI
+--headless           - Run without curses, printing all lines to stdout and
+                       reading commands from stdin
+--output <format>    - Output format in headless mode: text (default) or json
+--script <file>      - Read commands from <file> instead of stdin. Implies --headless.
+                       Lines starting with # are skipped, :sleep <ms> waits
+--help, -h           - Print this text
+
+Without any adapter option, a WebSocket adapter listens at 127.0.0.1:8080.
+";
+
+const DEFAULT_WS_ADDR: &str = "127.0.0.1:8080";
+const DEFAULT_MOCKS_DIR: &str = "./mocks";
+const DEFAULT_RULES_FILE: &str = "rules.json";
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub ws: Vec<String>,
+    pub wss: Vec<String>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub self_signed: bool,
+    pub handshake: HandshakeOptions,
+    pub connect: Vec<String>,
+    /// listen addresses and upstream urls of the proxies
+    pub proxy: Vec<(String, String)>,
+    /// addresses and framing of the TCP adapters
+    pub tcp: Vec<(String, Framing)>,
+    pub mocks: PathBuf,
+    /// request paths and the mock directories used for their clients
+    pub routes: Vec<(String, PathBuf)>,
+    pub rules: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub test_adapter: bool,
+    pub headless: bool,
+    pub output: OutputFormat,
+    pub script: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            ws: vec![],
+            wss: vec![],
+            cert: None,
+            key: None,
+            self_signed: false,
+            handshake: HandshakeOptions::default(),
+            connect: vec![],
+            proxy: vec![],
+            tcp: vec![],
+            mocks: PathBuf::from(DEFAULT_MOCKS_DIR),
+            routes: vec![],
+            rules: None,
+            record: None,
+            test_adapter: false,
+            headless: false,
+            output: OutputFormat::Text,
+            script: None,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut opts = Options::default();
+        let mut args = args.into_iter();
+        let mut framing = Framing::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ws" => opts.ws.push(Options::value(&arg, args.next())?),
+                "--wss" => opts.wss.push(Options::value(&arg, args.next())?),
+                "--cert" => opts.cert = Some(PathBuf::from(Options::value(&arg, args.next())?)),
+                "--key" => opts.key = Some(PathBuf::from(Options::value(&arg, args.next())?)),
+                "--self-signed" => opts.self_signed = true,
+                "--protocol" => opts.handshake.protocols.push(Options::value(&arg, args.next())?),
+                "--require-header" => {
+                    let value = Options::value(&arg, args.next())?;
+                    let header = match value.split_once('=') {
+                        Some((name, v)) => (String::from(name), Some(String::from(v))),
+                        None => (value, None),
+                    };
+                    opts.handshake.required_headers.push(header);
+                },
+                "--connect" => opts.connect.push(Options::value(&arg, args.next())?),
+                "--proxy" => {
+                    let value = Options::value(&arg, args.next())?;
+                    let (addr, url) = value.split_once('=')
+                        .ok_or_else(|| anyhow!("expected <addr>=<url> for --proxy, got {}", value))?;
+                    opts.proxy.push((String::from(addr), String::from(url)));
+                },
+                "--tcp" => opts.tcp.push((Options::value(&arg, args.next())?, framing)),
+                "--framing" => framing = Options::value(&arg, args.next())?.parse()?,
+                "--mocks" => opts.mocks = PathBuf::from(Options::value(&arg, args.next())?),
+                "--route" => {
+                    let value = Options::value(&arg, args.next())?;
+                    match value.split_once('=') {
+                        Some((path, dir)) if path.starts_with('/') => opts.routes.push((String::from(path), PathBuf::from(dir))),
+                        _ => return Err(anyhow!("expected /<path>=<dir> for --route, got {}", value)),
+                    }
+                },
+                "--rules" => opts.rules = Some(PathBuf::from(Options::value(&arg, args.next())?)),
+                "--record" => opts.record = Some(PathBuf::from(Options::value(&arg, args.next())?)),
+                "--test-adapter" => opts.test_adapter = true,
+                "--headless" => opts.headless = true,
+                "--output" => opts.output = Options::value(&arg, args.next())?.parse()?,
+                "--script" => {
+                    opts.script = Some(PathBuf::from(Options::value(&arg, args.next())?));
+                    opts.headless = true;
+                },
+                "--help" | "-h" => opts.help = true,
+                _ => return Err(anyhow!("unknown option: {}", arg)),
+            }
+        }
+
+        if opts.ws.is_empty() && opts.wss.is_empty() && opts.connect.is_empty() && opts.proxy.is_empty() && opts.tcp.is_empty() && !opts.test_adapter {
+            opts.ws.push(String::from(DEFAULT_WS_ADDR));
+        }
+        if !opts.wss.is_empty() && opts.cert.is_some() != opts.key.is_some() {
+            return Err(anyhow!("--cert and --key have to be given together"));
+        }
+        if !opts.wss.is_empty() && opts.cert.is_none() && !opts.self_signed {
+            return Err(anyhow!("--wss needs --cert and --key, or --self-signed"));
+        }
+
+        Ok(opts)
+    }
+
+    /// The rules file to load, if any. Without `--rules`, a `rules.json`
+    /// next to the mocks directory is picked up when it exists.
+    pub fn rules_file(&self) -> Option<PathBuf> {
+        if self.rules.is_some() {
+            return self.rules.clone();
+        }
+
+        let parent = self.mocks.parent().unwrap_or(&self.mocks);
+        let default = parent.join(DEFAULT_RULES_FILE);
+        default.exists().then_some(default)
+    }
+
+    fn value(flag: &str, value: Option<String>) -> anyhow::Result<String> {
+        match value {
+            Some(v) if !v.starts_with("--") => Ok(v),
+            _ => Err(anyhow!("missing value for {}", flag)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Options> {
+        Options::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn defaults_to_websocket_adapter() {
+        let opts = parse(&[]).unwrap();
+        assert_eq!(opts.ws, vec![DEFAULT_WS_ADDR]);
+        assert!(opts.tcp.is_empty());
+        assert_eq!(opts.mocks, PathBuf::from(DEFAULT_MOCKS_DIR));
+    }
+
+    #[test]
+    fn collects_repeated_adapters() {
+        let opts = parse(&["--tcp", "127.0.0.1:7000", "--tcp", "127.0.0.1:7001", "--mocks", "./fixtures"]).unwrap();
+        assert!(opts.ws.is_empty());
+        assert_eq!(opts.tcp, vec![(String::from("127.0.0.1:7000"), Framing::Json), (String::from("127.0.0.1:7001"), Framing::Json)]);
+        assert_eq!(opts.mocks, PathBuf::from("./fixtures"));
+    }
+
+    #[test]
+    fn framing_applies_to_following_tcp_adapters() {
+        let opts = parse(&["--tcp", "127.0.0.1:7000", "--framing", "ndjson", "--tcp", "127.0.0.1:7001"]).unwrap();
+        assert_eq!(opts.tcp, vec![(String::from("127.0.0.1:7000"), Framing::Json), (String::from("127.0.0.1:7001"), Framing::Lines)]);
+        assert!(parse(&["--framing", "xml"]).is_err());
+    }
+
+    #[test]
+    fn connect_alone_does_not_add_websocket_adapter() {
+        let opts = parse(&["--connect", "ws://127.0.0.1:9000/feed"]).unwrap();
+        assert_eq!(opts.connect, vec!["ws://127.0.0.1:9000/feed"]);
+        assert!(opts.ws.is_empty());
+    }
+
+    #[test]
+    fn parse_proxy_address_and_upstream() {
+        let opts = parse(&["--proxy", "127.0.0.1:8081=ws://localhost:9000/feed?a=b"]).unwrap();
+        assert_eq!(opts.proxy, vec![(String::from("127.0.0.1:8081"), String::from("ws://localhost:9000/feed?a=b"))]);
+        assert!(opts.ws.is_empty());
+        assert!(parse(&["--proxy", "127.0.0.1:8081"]).is_err());
+    }
+
+    #[test]
+    fn wss_needs_certificate() {
+        let opts = parse(&["--wss", "127.0.0.1:8443", "--self-signed"]).unwrap();
+        assert_eq!(opts.wss, vec!["127.0.0.1:8443"]);
+        assert!(opts.ws.is_empty());
+        assert!(parse(&["--wss", "127.0.0.1:8443", "--cert", "cert.pem", "--key", "key.pem"]).is_ok());
+        assert!(parse(&["--wss", "127.0.0.1:8443", "--cert", "cert.pem"]).is_err());
+        assert!(parse(&["--wss", "127.0.0.1:8443"]).is_err());
+    }
+
+    #[test]
+    fn parse_handshake_options_and_routes() {
+        let opts = parse(&["--protocol", "chat.v2", "--protocol", "chat", "--require-header", "Authorization",
+            "--require-header", "Origin=http://localhost:3000", "--route", "/ws/chat=./mocks/chat"]).unwrap();
+        assert_eq!(opts.handshake.protocols, vec!["chat.v2", "chat"]);
+        assert_eq!(opts.handshake.required_headers, vec![
+            (String::from("Authorization"), None),
+            (String::from("Origin"), Some(String::from("http://localhost:3000"))),
+        ]);
+        assert_eq!(opts.routes, vec![(String::from("/ws/chat"), PathBuf::from("./mocks/chat"))]);
+        assert!(parse(&["--route", "ws/chat=./mocks/chat"]).is_err());
+    }
+
+    #[test]
+    fn test_adapter_alone_does_not_add_websocket_adapter() {
+        let opts = parse(&["--test-adapter"]).unwrap();
+        assert!(opts.test_adapter);
+        assert!(opts.ws.is_empty());
+    }
+
+    #[test]
+    fn rejects_missing_value_and_unknown_options() {
+        assert!(parse(&["--ws"]).is_err());
+        assert!(parse(&["--ws", "--tcp", "127.0.0.1:7000"]).is_err());
+        assert!(parse(&["--port", "80"]).is_err());
+    }
+
+    #[test]
+    fn script_implies_headless() {
+        let opts = parse(&["--script", "smoke.txt", "--output", "json"]).unwrap();
+        assert!(opts.headless);
+        assert_eq!(opts.script, Some(PathBuf::from("smoke.txt")));
+        assert_eq!(opts.output, OutputFormat::JsonLines);
+        assert!(parse(&["--output", "xml"]).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use std::{env, fs, process::Command, sync::atomic::{AtomicUsize, Ordering}};
+use anyhow::anyhow;
+
+const DEFAULT_EDITOR: &str = "vi";
+
+static NEXT_SCRATCH: AtomicUsize = AtomicUsize::new(1);
+
+/// The editor from `$VISUAL` or `$EDITOR`, which may include arguments such as `code --wait`.
+pub fn editor_command() -> String {
+    env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| String::from(DEFAULT_EDITOR))
+}
+
+/// Lets the user edit `content` in a scratch file and returns the result.
+/// Blocks until the editor exits.
+pub fn edit(editor: &str, content: &str) -> anyhow::Result<String> {
+    let mut args = editor.split_whitespace();
+    let program = args.next().ok_or_else(|| anyhow!("no editor set, see $EDITOR"))?;
+
+    // .json, so editors pick the right syntax highlighting
+    let path = env::temp_dir().join(format!("termws-{}-{}.json", std::process::id(), NEXT_SCRATCH.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&path, content)?;
+
+    let status = Command::new(program)
+        .args(args)
+        .arg(&path)
+        .status()
+        .map_err(|e| anyhow!("could not run {}: {}", program, e));
+    let result = match status {
+        Ok(s) if s.success() => fs::read_to_string(&path).map_err(anyhow::Error::from),
+        Ok(s) => Err(anyhow!("{} exited with {}", program, s)),
+        Err(e) => Err(e),
+    };
+
+    let _ = fs::remove_file(&path);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn return_edited_content() {
+        assert_eq!(edit("sed -i s/1/2/", "{\"a\": 1}\n").unwrap(), "{\"a\": 2}\n");
+        assert!(edit("false", "{}").is_err());
+        assert!(edit("termws-no-such-editor", "{}").is_err());
+    }
+}
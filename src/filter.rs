@@ -0,0 +1,129 @@
+use anyhow::anyhow;
+use regex::Regex;
+
+use crate::{adapters::common::{ConnId, Line}, json::JsonValue, rules::Matcher};
+
+#[derive(Debug)]
+enum Condition {
+    /// messages and control lines in one direction, `true` for outgoing
+    Direction(bool),
+    Conn(ConnId),
+    Matcher(Matcher),
+}
+
+/// Decides which lines the UI shows, e.g. `in @ws#1 type=ping`.
+/// All conditions have to match.
+#[derive(Debug)]
+pub struct LineFilter {
+    spec: String,
+    conditions: Vec<Condition>,
+}
+
+impl LineFilter {
+    /// Parses space separated conditions:
+    ///
+    /// - `in`, `out`: direction of messages and control lines
+    /// - `@<client>`: lines of a single connection
+    /// - `<field>=<value>`, `/<pointer>=<value>`: json messages with that value,
+    ///   which is parsed as json if possible and taken as string otherwise
+    /// - anything else starts a regex spanning the rest of the spec
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let spec = spec.trim();
+        let mut conditions = vec![];
+        let mut rest = spec;
+
+        while !rest.is_empty() {
+            let (token, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            let condition = match token {
+                "in" => Condition::Direction(false),
+                "out" => Condition::Direction(true),
+                t if t.starts_with('@') && t.len() > 1 => Condition::Conn(ConnId::from(t)),
+                t if t.contains('=') && !t.starts_with('=') => {
+                    let (key, value) = t.split_once('=').unwrap_or((t, ""));
+                    let value = JsonValue::parse(value).unwrap_or_else(|_| JsonValue::String(String::from(value)));
+                    if key.starts_with('/') {
+                        Condition::Matcher(Matcher::Pointer(String::from(key), Some(value)))
+                    } else {
+                        Condition::Matcher(Matcher::Field(String::from(key), value))
+                    }
+                },
+                _ => {
+                    let re = Regex::new(rest).map_err(|e| anyhow!("invalid pattern {}: {}", rest, e))?;
+                    conditions.push(Condition::Matcher(Matcher::Regex(re)));
+                    break;
+                },
+            };
+            conditions.push(condition);
+            rest = tail.trim_start();
+        }
+
+        if conditions.is_empty() {
+            return Err(anyhow!("empty filter"));
+        }
+
+        Ok(LineFilter { spec: String::from(spec), conditions })
+    }
+
+    pub fn matches(&self, line: &Line) -> bool {
+        // parsed at most once, and only when a field has to be checked
+        let mut json = None;
+
+        self.conditions.iter().all(|c| match c {
+            Condition::Direction(outgoing) => {
+                (line.is_message() || line.control) && line.outgoing() == *outgoing
+            },
+            Condition::Conn(id) => line.conn.as_ref() == Some(id),
+            Condition::Matcher(m) => {
+                let json = json.get_or_insert_with(|| {
+                    let text = line.raw.as_ref().unwrap_or(&line.text);
+                    line.treat_as_json.then(|| JsonValue::parse(text).ok()).flatten()
+                });
+                m.matches(&line.text, json.as_ref())
+            },
+        })
+    }
+}
+
+impl std::fmt::Display for LineFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::common::Direction;
+
+    fn incoming(text: &str, conn: &str) -> Line {
+        Line::new_json(String::from(text), Direction::Incoming).with_conn(&ConnId::from(conn))
+    }
+
+    #[test]
+    fn match_direction_and_connection() {
+        let f = LineFilter::parse("in @ws#1").unwrap();
+        assert!(f.matches(&incoming("{}", "ws#1")));
+        assert!(!f.matches(&incoming("{}", "ws#2")));
+        assert!(!f.matches(&Line::new_log(String::from("client connected")).with_conn(&ConnId::from("ws#1"))));
+    }
+
+    #[test]
+    fn match_field_values() {
+        let f = LineFilter::parse("type=ping").unwrap();
+        assert!(f.matches(&incoming("{\"type\": \"ping\"}", "ws#1")));
+        assert!(!f.matches(&incoming("{\"type\": \"pong\"}", "ws#1")));
+
+        let f = LineFilter::parse("/user/id=42").unwrap();
+        assert!(f.matches(&incoming("{\"user\": {\"id\": 42}}", "ws#1")));
+        assert!(!f.matches(&incoming("{\"user\": {\"id\": \"42\"}}", "ws#1")));
+    }
+
+    #[test]
+    fn regex_takes_rest_of_spec() {
+        let f = LineFilter::parse("@ws#1 timed out|failed").unwrap();
+        assert!(f.matches(&Line::new_log(String::from("request timed out")).with_conn(&ConnId::from("ws#1"))));
+        assert!(!f.matches(&Line::new_log(String::from("request timed out"))));
+        assert!(LineFilter::parse("(").is_err());
+        assert!(LineFilter::parse(" ").is_err());
+    }
+}
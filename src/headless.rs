@@ -0,0 +1,164 @@
+use std::{collections::VecDeque, fs, io::{BufRead, ErrorKind, Write}, path::Path, sync::mpsc::{self, Receiver, TryRecvError}, thread, time::{Duration, Instant}};
+use anyhow::anyhow;
+
+use crate::{adapters::common::Line, binary::BinaryView, ui::Frontend};
+
+const SLEEP_COMMAND: &str = ":sleep";
+const EXIT_COMMAND: &str = ":exit";
+// bytes of a binary message printed in text output
+const BINARY_PREVIEW: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    JsonLines,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" | "jsonl" => Ok(OutputFormat::JsonLines),
+            _ => Err(anyhow!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Frontend without curses. Prints every line to stdout and reads
+/// commands from a script file, or from stdin when there is no script.
+///
+/// Scripts contain one command per line. Empty lines and lines starting
+/// with `#` are skipped, and `:sleep <ms>` pauses before the next command.
+pub struct HeadlessUI {
+    format: OutputFormat,
+    script: VecDeque<String>,
+    stdin: Option<Receiver<String>>,
+    sleep_until: Option<Instant>,
+    // stdout was closed, e.g. by `| head`, so the session ends
+    closed: bool,
+}
+
+impl HeadlessUI {
+    pub fn new(format: OutputFormat, script: Option<&Path>) -> anyhow::Result<Self> {
+        let (script, stdin) = match script {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| anyhow!("could not read script {}: {}", path.display(), e))?;
+                let lines = content.lines()
+                    .map(|l| String::from(l.trim()))
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .collect();
+                (lines, None)
+            },
+            None => (VecDeque::new(), Some(HeadlessUI::read_stdin())),
+        };
+
+        Ok(HeadlessUI {
+            format,
+            script,
+            stdin,
+            sleep_until: None,
+            closed: false,
+        })
+    }
+
+    fn read_stdin() -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        rx
+    }
+
+    fn next_command(&mut self) -> Option<String> {
+        if let Some(ref rx) = self.stdin {
+            match rx.try_recv() {
+                Ok(command) => return Some(command),
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => {
+                    // stdin was closed, keep serving without commands
+                    self.stdin = None;
+                },
+            }
+        }
+
+        self.script.pop_front()
+    }
+
+    fn format_text(line: &Line) -> String {
+        let arrow = match (line.is_message(), line.outgoing()) {
+            (false, true) if line.control => "*>",
+            (false, false) if line.control => "<*",
+            (false, _) => "  ",
+            (true, true) => "->",
+            (true, false) => "<-",
+        };
+
+        let mut text = match line.conn {
+            Some(ref conn) => format!("{} {} [{}] {}", line.format_date(), arrow, conn, line.text),
+            None => format!("{} {} {}", line.format_date(), arrow, line.text),
+        };
+        if let Some(ref bytes) = line.binary {
+            text.push_str(&format!(": {}", BinaryView::Hex.preview(bytes, BINARY_PREVIEW)));
+        }
+        if let Some(ref e) = line.json_error {
+            text.push_str(&format!("  !! invalid json: {}", e));
+        }
+
+        text
+    }
+}
+
+impl Frontend for HeadlessUI {
+    fn add_line(&mut self, line: Line) {
+        if self.closed {
+            return;
+        }
+
+        let text = match self.format {
+            OutputFormat::Text => HeadlessUI::format_text(&line),
+            OutputFormat::JsonLines => line.to_json().to_string(),
+        };
+        let mut out = std::io::stdout().lock();
+        match writeln!(out, "{}", text).and_then(|_| out.flush()) {
+            Ok(_) => {},
+            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => self.closed = true,
+            Err(e) => eprintln!("could not write output: {}", e),
+        }
+    }
+
+    fn handle_keyboard(&mut self) -> Option<String> {
+        if self.closed {
+            return Some(String::from(EXIT_COMMAND));
+        }
+
+        if let Some(until) = self.sleep_until {
+            if Instant::now() < until {
+                return None;
+            }
+            self.sleep_until = None;
+        }
+
+        let command = self.next_command()?;
+        if let Some((SLEEP_COMMAND, ms)) = command.trim().split_once(' ') {
+            match ms.trim().parse::<u64>() {
+                Ok(ms) => self.sleep_until = Some(Instant::now() + Duration::from_millis(ms)),
+                Err(_) => self.add_line(Line::new_log(format!("invalid sleep duration: {}", ms.trim()))),
+            }
+            return None;
+        }
+
+        Some(command)
+    }
+}
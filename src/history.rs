@@ -0,0 +1,72 @@
+use std::{env, fs::{self, OpenOptions}, io::Write, path::PathBuf};
+
+const HISTORY_FILE: &str = ".termws_history";
+const MAX_ENTRIES: usize = 500;
+
+/// Commands entered in earlier and current runs, oldest first.
+/// Every new entry is appended to the history file right away.
+#[derive(Debug, Default)]
+pub struct History {
+    pub entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// `~/.termws_history`, if there is a home directory.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("HOME").map(|h| PathBuf::from(h).join(HISTORY_FILE))
+    }
+
+    /// Loads the last entries of `path`. A missing file is an empty history.
+    pub fn load(path: PathBuf) -> Self {
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let mut entries: Vec<String> = content.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(String::from)
+            .collect();
+        let excess = entries.len().saturating_sub(MAX_ENTRIES);
+        entries.drain(..excess);
+
+        History { entries, path: Some(path) }
+    }
+
+    pub fn push(&mut self, entry: &str) {
+        if entry.trim().is_empty() || self.entries.last().is_some_and(|l| l == entry) {
+            return;
+        }
+
+        self.entries.push(String::from(entry));
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+
+        if let Some(ref path) = self.path {
+            let r = OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut f| writeln!(f, "{}", entry));
+            if let Err(e) = r {
+                eprintln!("could not save history to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persist_entries() {
+        let path = env::temp_dir().join(format!("termws-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut h = History::load(path.clone());
+        assert!(h.entries.is_empty());
+        h.push(":ls");
+        h.push(":ls");
+        h.push(":send test.json");
+
+        let h = History::load(path.clone());
+        assert_eq!(h.entries, vec![":ls", ":send test.json"]);
+        fs::remove_file(&path).unwrap();
+    }
+}
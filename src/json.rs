@@ -1,44 +1,552 @@
-use anyhow::anyhow;
-
 #[derive(Debug, Default)]
 pub struct JsonFormatter;
 
+/// Why and where a payload failed to parse as json.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    /// byte offset into the input
+    pub offset: usize,
+    /// 1-based line and column (in characters) of `offset`
+    pub line: usize,
+    pub column: usize,
+    pub reason: String,
+}
+
+impl JsonError {
+    fn new(src: &str, offset: usize, reason: String) -> Self {
+        let before = src.get(..offset).unwrap_or(src);
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+
+        JsonError {
+            offset,
+            line,
+            column,
+            reason,
+        }
+    }
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at line {}, column {}", self.reason, self.line, self.column)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
 impl JsonFormatter {
-    pub fn format(&mut self, s: &String) -> anyhow::Result<String> {
+    /// Validates `s` and strips all whitespace outside of strings.
+    pub fn format(&mut self, s: &str) -> Result<String, JsonError> {
+        JsonValue::parse(s)?;
 
-        let mut r = String::new();
+        let mut r = String::with_capacity(s.len());
         let mut inside_string = false;
         let mut escaping = false;
 
         for c in s.chars() {
-            match c {
+            if inside_string {
+                r.push(c);
+                match (c, escaping) {
+                    ('"', false) => inside_string = false,
+                    ('\\', false) => escaping = true,
+                    _ => escaping = false,
+                }
+            } else if !c.is_whitespace() {
+                r.push(c);
+                inside_string = c == '"';
+            }
+        }
+
+        Ok(r)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Key,
+    String,
+    Number,
+    Bool,
+    Null,
+    Punctuation,
+}
+
+/// A row of highlighted text, as produced by `JsonFormatter::highlight`.
+pub type Row = Vec<(TokenKind, String)>;
+
+const INDENT: &str = "  ";
+
+impl JsonFormatter {
+    /// `s` indented over multiple lines, as shown by `highlight` with `pretty`.
+    pub fn pretty(&self, s: &str) -> String {
+        let rows: Vec<String> = self.highlight(s, true).iter()
+            .map(|r| r.iter().map(|(_, t)| t.as_str()).collect())
+            .collect();
+        rows.join("\n")
+    }
+
+    /// Splits `s` into highlighted tokens. With `pretty`, objects and arrays
+    /// are broken into indented rows, otherwise everything is in one row.
+    /// Input that is not valid json is highlighted on a best effort basis.
+    pub fn highlight(&self, s: &str, pretty: bool) -> Vec<Row> {
+        let chars: Vec<char> = s.chars().collect();
+        let mut rows: Vec<Row> = vec![vec![]];
+        let mut depth = 0usize;
+        let mut i = 0;
+
+        let next_non_ws = |from: usize| chars[from..].iter().copied().find(|c| !c.is_whitespace());
+
+        while i < chars.len() {
+            let c = chars[i];
+            let start = i;
+            i += 1;
+
+            let kind = match c {
                 '"' => {
-                    r.push(c);
-                    if escaping {
-                        continue;
+                    let mut escaping = false;
+                    while i < chars.len() {
+                        let c = chars[i];
+                        i += 1;
+                        match (c, escaping) {
+                            ('"', false) => break,
+                            ('\\', false) => escaping = true,
+                            _ => escaping = false,
+                        }
+                    }
+
+                    if next_non_ws(i) == Some(':') {
+                        TokenKind::Key
                     } else {
-                        inside_string = !inside_string;
+                        TokenKind::String
+                    }
+                },
+                '-' | '0'..='9' => {
+                    while i < chars.len() && matches!(chars[i], '0'..='9' | '.' | 'e' | 'E' | '+' | '-') {
+                        i += 1;
                     }
+                    TokenKind::Number
                 },
-                '\\' => {
-                    r.push(c);
-                    if inside_string {
-                        escaping = true;
+                c if c.is_alphabetic() => {
+                    while i < chars.len() && chars[i].is_alphanumeric() {
+                        i += 1;
+                    }
+                    match chars[start..i].iter().collect::<String>().as_str() {
+                        "null" => TokenKind::Null,
+                        _ => TokenKind::Bool,
+                    }
+                },
+                c if c.is_whitespace() => continue,
+                _ => TokenKind::Punctuation,
+            };
+
+            let token: String = chars[start..i].iter().collect();
+            let row = rows.last_mut().expect("at least one row");
+
+            if !pretty || kind != TokenKind::Punctuation {
+                row.push((kind, token));
+                continue;
+            }
+
+            match c {
+                '{' | '[' => {
+                    row.push((kind, token));
+                    let close = if c == '{' { '}' } else { ']' };
+                    if next_non_ws(i) == Some(close) {
+                        // keep empty objects and arrays on one row
+                        row.push((kind, close.to_string()));
+                        i = i + chars[i..].iter().position(|c| *c == close).unwrap_or(0) + 1;
                     } else {
-                        return Err(anyhow!("Unexpected '\\'"));
+                        depth += 1;
+                        rows.push(vec![(TokenKind::Punctuation, INDENT.repeat(depth))]);
                     }
                 },
-                c => {
-                    if !c.is_control() {
-                        if inside_string || !c.is_whitespace() {
-                            r.push(c);
-                        }
+                '}' | ']' => {
+                    depth = depth.saturating_sub(1);
+                    if row.iter().all(|(_, t)| t.trim().is_empty()) {
+                        row.clear();
+                        row.push((TokenKind::Punctuation, INDENT.repeat(depth)));
+                    } else {
+                        rows.push(vec![(TokenKind::Punctuation, INDENT.repeat(depth))]);
                     }
+                    rows.last_mut().expect("at least one row").push((kind, token));
+                },
+                ',' => {
+                    row.push((kind, token));
+                    rows.push(vec![(TokenKind::Punctuation, INDENT.repeat(depth))]);
                 },
+                ':' => row.push((kind, String::from(": "))),
+                _ => row.push((kind, token)),
             }
         }
 
-        Ok(r)
+        rows
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Parses `s` strictly according to RFC 8259.
+    pub fn parse(s: &str) -> Result<JsonValue, JsonError> {
+        let mut p = JsonParser { src: s, bytes: s.as_bytes(), pos: 0, depth: 0 };
+        p.skip_whitespace();
+        let v = p.parse_value()?;
+        p.skip_whitespace();
+        if p.pos < p.bytes.len() {
+            return Err(p.unexpected("end of input"));
+        }
+        Ok(v)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Resolves a JSON pointer (RFC 6901) such as `/user/roles/0`.
+    pub fn pointer(&self, pointer: &str) -> Option<&JsonValue> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+
+        let mut v = self;
+        for token in pointer.strip_prefix('/')?.split('/') {
+            let token = token.replace("~1", "/").replace("~0", "~");
+            v = match v {
+                JsonValue::Object(_) => v.get(&token)?,
+                JsonValue::Array(items) => items.get(token.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+
+        Some(v)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(b) => write!(f, "{}", b),
+            JsonValue::Number(n) => write!(f, "{}", n),
+            JsonValue::String(s) => write!(f, "{}", escape_string(s)),
+            JsonValue::Array(items) => {
+                write!(f, "[")?;
+                for (i, v) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            },
+            JsonValue::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", escape_string(k), v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+/// Quotes `s` as a JSON string literal.
+pub fn escape_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+// deeper nesting is rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+struct JsonParser<'a> {
+    src: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, reason: &str) -> JsonError {
+        JsonError::new(self.src, self.pos, String::from(reason))
+    }
+
+    fn unexpected(&self, expected: &str) -> JsonError {
+        let found = match self.src.get(self.pos..).and_then(|s| s.chars().next()) {
+            Some(c) => format!("unexpected {:?}", c),
+            None => String::from("unexpected end of input"),
+        };
+        self.error(&format!("{}, expected {}", found, expected))
+    }
+
+    fn enter(&mut self) -> Result<(), JsonError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), JsonError> {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", b as char)))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, JsonError> {
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(b't') => self.parse_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.parse_literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.parse_literal("null", JsonValue::Null),
+            _ => Err(self.unexpected("a value")),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, v: JsonValue) -> Result<JsonValue, JsonError> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(v)
+        } else {
+            Err(self.error(&format!("invalid literal, expected {}", literal)))
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect(b'{')?;
+        self.enter()?;
+        let mut members = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            self.depth -= 1;
+            return Ok(JsonValue::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected("a string as object key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(JsonValue::Object(members));
+                },
+                _ => return Err(self.unexpected("',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect(b'[')?;
+        self.enter()?;
+        let mut items = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            self.depth -= 1;
+            return Ok(JsonValue::Array(items));
+        }
+
+        loop {
+            self.skip_whitespace();
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(JsonValue::Array(items));
+                },
+                _ => return Err(self.unexpected("',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.pos;
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.skip_digits(),
+            _ => return Err(self.unexpected("a digit")),
+        }
+
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.unexpected("a digit after the decimal point"));
+            }
+            self.skip_digits();
+        }
+
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.unexpected("a digit in the exponent"));
+            }
+            self.skip_digits();
+        }
+
+        self.src[start..self.pos].parse::<f64>()
+            .map(JsonValue::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn skip_digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut r = String::new();
+        let mut start = self.pos;
+
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    r.push_str(&self.src[start..self.pos]);
+                    self.pos += 1;
+                    return Ok(r);
+                },
+                Some(b'\\') => {
+                    r.push_str(&self.src[start..self.pos]);
+                    self.pos += 1;
+                    self.parse_escape(&mut r)?;
+                    start = self.pos;
+                },
+                Some(b) if b < 0x20 => return Err(self.error("unescaped control character in string")),
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+
+    fn parse_escape(&mut self, r: &mut String) -> Result<(), JsonError> {
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                let hi = self.parse_hex4()?;
+                let code = if (0xD800..0xDC00).contains(&hi) {
+                    if !self.bytes[self.pos..].starts_with(b"\\u") {
+                        return Err(self.error("unpaired surrogate in escape"));
+                    }
+                    self.pos += 2;
+                    let lo = self.parse_hex4()?;
+                    if !(0xDC00..0xE000).contains(&lo) {
+                        return Err(self.error("invalid low surrogate in escape"));
+                    }
+                    0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+                } else {
+                    hi
+                };
+                let c = char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))?;
+                r.push(c);
+                return Ok(());
+            },
+            _ => return Err(self.error("invalid escape sequence")),
+        };
+
+        r.push(c);
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self.src.get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("incomplete unicode escape"))?;
+        // from_str_radix would accept a sign, as in \u+12a
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(self.error("invalid unicode escape"));
+        }
+        let code = u32::from_str_radix(digits, 16)
+            .map_err(|_| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(code)
     }
 }
 
@@ -48,7 +556,7 @@ mod tests {
 
     #[test]
     fn format_string_literal()  {
-        let mut fmt = JsonFormatter::default();
+        let mut fmt = JsonFormatter;
         let inp = String::from("\"This is string\"");
         let out = fmt.format(&inp).unwrap();
         assert_eq!(inp, out);
@@ -56,7 +564,7 @@ mod tests {
 
     #[test]
     fn format_string_literal_preserve_whitespace()  {
-        let mut fmt = JsonFormatter::default();
+        let mut fmt = JsonFormatter;
         let inp = String::from("\"This is string\"");
         let out = fmt.format(&inp).unwrap();
         assert_eq!(inp, out);
@@ -65,7 +573,7 @@ mod tests {
 
     #[test]
     fn format_simple_array()  {
-        let mut fmt = JsonFormatter::default();
+        let mut fmt = JsonFormatter;
         let inp = String::from("[1,2]");
         let out = fmt.format(&inp).unwrap();
         assert_eq!(inp, out);
@@ -73,10 +581,107 @@ mod tests {
 
     #[test]
     fn format_simple_array_with_line_break()  {
-        let mut fmt = JsonFormatter::default();
+        let mut fmt = JsonFormatter;
         let inp = String::from("[1,\n2]");
         let out = fmt.format(&inp).unwrap();
         assert_eq!(String::from("[1,2]"), out);
     }
-}
 
+    #[test]
+    fn parse_nested_value() {
+        let v = JsonValue::parse("{ \"a\": [1, -2.5e1, true, null], \"b\": { \"c\": \"x\\ny\" } }").unwrap();
+        assert_eq!(v.pointer("/a/1"), Some(&JsonValue::Number(-25.0)));
+        assert_eq!(v.pointer("/a/3"), Some(&JsonValue::Null));
+        assert_eq!(v.pointer("/b/c").and_then(|v| v.as_str()), Some("x\ny"));
+        assert_eq!(v.pointer("/b/d"), None);
+    }
+
+    #[test]
+    fn parse_unicode_escapes() {
+        let v = JsonValue::parse("\"\\u00e4\\ud83d\\ude00\"").unwrap();
+        assert_eq!(v, JsonValue::String(String::from("ä😀")));
+        assert!(JsonValue::parse("\"\\u+12a\"").is_err());
+        assert!(JsonValue::parse("\"\\u-12a\"").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_json() {
+        for inp in ["", "{", "[1,]", "{\"a\":1,}", "}{", "tru", "01", "[1] x", "{a:1}"] {
+            assert!(JsonValue::parse(inp).is_err(), "{} should be rejected", inp);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let inp = "{\"a\":[1,2.5,\"q\\\"\"],\"b\":null}";
+        let v = JsonValue::parse(inp).unwrap();
+        assert_eq!(v.to_string(), inp);
+    }
+
+    fn row_texts(rows: &[Row]) -> Vec<String> {
+        rows.iter()
+            .map(|r| r.iter().map(|(_, t)| t.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn highlight_token_kinds() {
+        let rows = JsonFormatter.highlight("{\"a\":\"b\",\"n\":-1.5e3,\"t\":true,\"z\":null}", false);
+        assert_eq!(rows.len(), 1);
+        let kinds: Vec<TokenKind> = rows[0].iter().map(|(k, _)| *k).filter(|k| *k != TokenKind::Punctuation).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::Key, TokenKind::String,
+            TokenKind::Key, TokenKind::Number,
+            TokenKind::Key, TokenKind::Bool,
+            TokenKind::Key, TokenKind::Null,
+        ]);
+    }
+
+    #[test]
+    fn highlight_pretty_indents_nested_values() {
+        let rows = JsonFormatter.highlight("{\"a\":[1,{}],\"b\":\"x,\\\"y\"}", true);
+        assert_eq!(row_texts(&rows), vec![
+            "{",
+            "  \"a\": [",
+            "    1,",
+            "    {}",
+            "  ],",
+            "  \"b\": \"x,\\\"y\"",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn format_rejects_invalid_json() {
+        for inp in ["{\"a\":1", "[1,2,]", "hello", "}{", "{\"a\" 1}", "\"tab\there\""] {
+            assert!(JsonFormatter.format(inp).is_err(), "{} should be rejected", inp);
+        }
+    }
+
+    #[test]
+    fn format_string_with_escaped_quote() {
+        let out = JsonFormatter.format("{ \"a\": \"x\\\" y\", \"b\" : 1 }").unwrap();
+        assert_eq!(out, "{\"a\":\"x\\\" y\",\"b\":1}");
+    }
+
+    #[test]
+    fn error_reports_position_and_reason() {
+        let e = JsonValue::parse("{\n  \"a\": 1,\n}").unwrap_err();
+        assert_eq!((e.line, e.column, e.offset), (3, 1, 12));
+        assert_eq!(e.to_string(), "unexpected '}', expected a string as object key at line 3, column 1");
+
+        let e = JsonValue::parse("[1, 2").unwrap_err();
+        assert_eq!(e.reason, "unexpected end of input, expected ',' or ']'");
+
+        let e = JsonValue::parse("{} x").unwrap_err();
+        assert_eq!((e.column, e.reason.as_str()), (4, "unexpected 'x', expected end of input"));
+    }
+
+    #[test]
+    fn reject_deep_nesting() {
+        let inp = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert_eq!(JsonValue::parse(&inp).unwrap_err().reason, "nesting too deep");
+        let inp = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(JsonValue::parse(&inp).is_ok());
+    }
+}
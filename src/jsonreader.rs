@@ -1,11 +1,29 @@
 use std::result::Result;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SplitError {
+    /// the stream contained a character that cannot start or continue a json value
     Tainted,
+    /// a single value grew beyond `max_size` bytes
+    TooLarge(usize),
+    /// the stream ended in the middle of a value
+    Incomplete,
+    /// a complete value was not valid UTF-8
+    InvalidUtf8,
+}
+
+impl std::fmt::Display for SplitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SplitError::Tainted => write!(f, "unexpected data outside of a json value"),
+            SplitError::TooLarge(max) => write!(f, "message exceeds {} bytes", max),
+            SplitError::Incomplete => write!(f, "stream ended inside a message"),
+            SplitError::InvalidUtf8 => write!(f, "message is not valid UTF-8"),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum ParseMode {
     ObjectOrArray,
     StringLiteral,
@@ -13,6 +31,9 @@ enum ParseMode {
     Undetermined,
 }
 
+/// Splits a stream of concatenated json values into single messages.
+/// Values may be separated by whitespace or NUL bytes, and may arrive
+/// split across any number of `push` calls.
 #[derive(Debug, Clone)]
 pub struct JsonSplitter {
     max_size: usize,
@@ -20,39 +41,47 @@ pub struct JsonSplitter {
     depth: u32,
     mode: ParseMode,
     escaping_next_char: bool,
+    // the current value grew too large, its bytes are dropped until it ends
+    oversized: bool,
     tainted: bool,
+    // values completed before an error, returned after `reset`
+    ready: Vec<String>,
+    // values dropped on their own, the stream goes on after them
+    skipped: Vec<SplitError>,
 }
 
-const SYM_QUOTES: char = '"';
-const SYM_OPEN_CURLY: char = '{';
-const SYM_CLOSE_CURLY: char = '}';
-const SYM_OPEN_BRACKET: char = '[';
-const SYM_CLOSE_BRACKET: char = ']';
-const SYM_BACKSLASH: char = '\\';
-
-const ROOT_LEVEL_CHARS: [char; 22] = [
-    't',
-    'r',
-    'u',
-    'e',
-    'E',
-    'f',
-    'a',
-    'l',
-    's',
-    'n',
-    '-',
-    '.',
-    '0',
-    '1',
-    '2',
-    '3',
-    '4',
-    '5',
-    '6',
-    '7',
-    '8',
-    '9',
+const SYM_QUOTES: u8 = b'"';
+const SYM_OPEN_CURLY: u8 = b'{';
+const SYM_CLOSE_CURLY: u8 = b'}';
+const SYM_OPEN_BRACKET: u8 = b'[';
+const SYM_CLOSE_BRACKET: u8 = b']';
+const SYM_BACKSLASH: u8 = b'\\';
+const SYM_NUL: u8 = b'\0';
+
+const ROOT_LEVEL_CHARS: [u8; 23] = [
+    b't',
+    b'r',
+    b'u',
+    b'e',
+    b'E',
+    b'f',
+    b'a',
+    b'l',
+    b's',
+    b'n',
+    b'-',
+    b'+',
+    b'.',
+    b'0',
+    b'1',
+    b'2',
+    b'3',
+    b'4',
+    b'5',
+    b'6',
+    b'7',
+    b'8',
+    b'9',
 ];
 
 impl JsonSplitter {
@@ -63,7 +92,10 @@ impl JsonSplitter {
             depth: 0,
             mode: ParseMode::Undetermined,
             escaping_next_char: false,
+            oversized: false,
             tainted: false,
+            ready: vec![],
+            skipped: vec![],
         }
     }
 
@@ -73,146 +105,156 @@ impl JsonSplitter {
             return Err(SplitError::Tainted);
         }
 
-        let mut r = vec![];
-        let mut i = 0;
-        let mut start = 0;
-        while i < buffer.len() {
-
-            // todo: properly grab chars
-            // depending on encoding
-            let c = buffer[i] as char;
+        for &c in buffer {
+            // a root level value ends with the first character
+            // which cannot be part of it, which is then
+            // processed as start of the next value
+            if self.mode == ParseMode::RootLevelValue && !JsonSplitter::is_root_level_char(c) {
+                self.complete();
+            }
 
             match self.mode {
                 ParseMode::Undetermined => {
                     match c {
                         SYM_OPEN_CURLY | SYM_OPEN_BRACKET => {
                             self.mode = ParseMode::ObjectOrArray;
-                            self.depth = self.depth + 1;
-                            start = i;
-                        },
-                        SYM_CLOSE_CURLY | SYM_CLOSE_BRACKET => {
-                            self.tainted = true;
-                            return Err(SplitError::Tainted);
+                            self.depth = 1;
                         },
                         SYM_QUOTES => {
                             self.mode = ParseMode::StringLiteral;
-                            self.depth = self.depth + 1;
+                            self.depth = 0;
+                        },
+                        c if JsonSplitter::is_root_level_char(c) => {
+                            self.mode = ParseMode::RootLevelValue;
+                        },
+                        c if c.is_ascii_whitespace() || c == SYM_NUL => {
+                            continue;
                         },
                         _ => {
-                            if !c.is_whitespace() {
-                                self.mode = ParseMode::RootLevelValue;
-                                start = i;
-                            }
+                            return Err(self.taint());
                         },
                     }
                 },
                 ParseMode::StringLiteral => {
                     match (c, self.escaping_next_char) {
-                        (SYM_QUOTES, true) => {
-                            // ignore escaped quotes
-                            if self.escaping_next_char {
-                                self.escaping_next_char = false;
-                            }
-                        },
                         (SYM_QUOTES, false) => {
-                            // we have as single string 
-                            // as complete json payload
-                            // therefore, extract the message
-                            if self.depth == 1 {
-                                let s = String::from_utf8_lossy(&buffer[start..i+1]);
-                                r.push(s.to_string());
-                                start = i + 1;
-                                self.depth = 0;
-                                self.mode = ParseMode::Undetermined;
+                            if self.depth == 0 {
+                                // a single string as complete json payload
+                                self.buffer.push(c);
+                                self.complete();
+                                continue;
                             } else {
-                                self.depth = self.depth - 1;
                                 self.mode = ParseMode::ObjectOrArray;
                             }
                         },
-                        (SYM_BACKSLASH,_) => {
-                            self.escaping_next_char = !self.escaping_next_char;
+                        (SYM_BACKSLASH, false) => {
+                            self.escaping_next_char = true;
+                        },
+                        _ => {
+                            self.escaping_next_char = false;
                         },
-                        _ => {},
-                    }
-                },
-                ParseMode::RootLevelValue => {
-                    if !JsonSplitter::is_root_level_char(c) {
-                        let s = String::from_utf8_lossy(&buffer[start..i+1]);
-                        r.push(s.to_string());
-                        start = i + 1;
                     }
                 },
+                ParseMode::RootLevelValue => {},
                 ParseMode::ObjectOrArray => {
                     match c {
                         SYM_QUOTES => {
                             self.mode = ParseMode::StringLiteral;
-                            self.depth = self.depth + 1;
                         },
                         SYM_OPEN_CURLY | SYM_OPEN_BRACKET => {
-                            self.depth = self.depth + 1;
+                            self.depth += 1;
                         },
                         SYM_CLOSE_BRACKET | SYM_CLOSE_CURLY => {
+                            self.depth -= 1;
                             if self.depth == 0 {
-                                // we had closing bracket, but no openening bracket
-                                self.tainted = true;
-                                return Err(SplitError::Tainted);
-                            } else if self.depth == 1 {
-                                let s = String::from_utf8_lossy(&buffer[start..i+1]);
-                                r.push(s.to_string());
-                                start = i + 1;
-                                self.depth = 0;
-                                self.mode = ParseMode::Undetermined;
-                            } else {
-                                self.depth = self.depth - 1 ;
+                                self.buffer.push(c);
+                                self.complete();
+                                continue;
                             }
                         },
                         _ => {},
                     }
                 },
             }
-            i = i + 1;
-        }
-
-        if start < buffer.len() {
 
+            if self.oversized {
+                continue;
+            }
+            self.buffer.push(c);
+            if self.buffer.len() > self.max_size {
+                // keep following the value, so the stream goes on after it
+                self.buffer.clear();
+                self.oversized = true;
+                self.skipped.push(SplitError::TooLarge(self.max_size));
+            }
         }
 
-        Ok(r)
+        Ok(std::mem::take(&mut self.ready))
     }
 
-    fn flush(&mut self) -> Result<Vec<String>, SplitError> {
-        let r = vec![];
-
+    /// Ends the stream. Returns a trailing root level value, such as
+    /// a number, which could not be terminated before.
+    pub fn flush(&mut self) -> Result<Vec<String>, SplitError> {
         if self.tainted {
             return Err(SplitError::Tainted);
         }
 
         match self.mode {
             ParseMode::Undetermined => {},
-            ParseMode::ObjectOrArray => {},
-            ParseMode::RootLevelValue => {
+            ParseMode::RootLevelValue => self.complete(),
+            ParseMode::ObjectOrArray | ParseMode::StringLiteral => {
+                self.taint();
+                return Err(SplitError::Incomplete);
             },
         }
 
-        Ok(r)
+        Ok(std::mem::take(&mut self.ready))
     }
 
-    fn push_and_flush(&mut self, buffer: &[u8]) -> Result<Vec<String>, SplitError> {
-        match self.push(buffer) {
-            Ok(mut s) => {
-                match self.flush() {
-                    Ok(ref mut s2) => {
-                        s.append(s2);
-                        Ok(s)
-                    },
-                    e => e,
-                }
-            },
-            e => e,
+    pub fn push_and_flush(&mut self, buffer: &[u8]) -> Result<Vec<String>, SplitError> {
+        let mut s = self.push(buffer)?;
+        s.append(&mut self.flush()?);
+        Ok(s)
+    }
+
+    /// Discards the partial value and recovers from errors. Values
+    /// completed before the error are returned by the next call.
+    pub fn reset(&mut self) {
+        let ready = std::mem::take(&mut self.ready);
+        let skipped = std::mem::take(&mut self.skipped);
+        *self = JsonSplitter::new(self.max_size);
+        self.ready = ready;
+        self.skipped = skipped;
+    }
+
+    /// Why values were dropped since the last call. Unlike errors
+    /// returned by `push`, these did not affect the values around them.
+    pub fn take_skipped(&mut self) -> Vec<SplitError> {
+        std::mem::take(&mut self.skipped)
+    }
+
+    fn complete(&mut self) {
+        let bytes = std::mem::take(&mut self.buffer);
+        self.mode = ParseMode::Undetermined;
+        self.depth = 0;
+        self.escaping_next_char = false;
+        if std::mem::take(&mut self.oversized) {
+            return;
+        }
+
+        match String::from_utf8(bytes) {
+            Ok(msg) => self.ready.push(msg),
+            Err(_) => self.skipped.push(SplitError::InvalidUtf8),
         }
     }
 
-    fn is_root_level_char(c: char) -> bool {
+    fn taint(&mut self) -> SplitError {
+        self.tainted = true;
+        self.buffer.clear();
+        SplitError::Tainted
+    }
+
+    fn is_root_level_char(c: u8) -> bool {
         ROOT_LEVEL_CHARS.contains(&c)
     }
 }
@@ -232,7 +274,7 @@ mod tests {
         let mut splitter = JsonSplitter::default();
         let a = "\"prop\"";
         let messages  = splitter.push(a.as_bytes()).unwrap();
-        assert!(messages.len() > 0);
+        assert!(!messages.is_empty());
         assert_eq!(messages[0], a);
     }
 
@@ -276,7 +318,7 @@ mod tests {
         let mut splitter = JsonSplitter::default();
         let a = "{\"prop\": 1}";
         let messages  = splitter.push(a.as_bytes()).unwrap();
-        assert!(messages.len() > 0);
+        assert!(!messages.is_empty());
         assert_eq!(messages[0], a);
     }
 
@@ -285,7 +327,76 @@ mod tests {
         let mut splitter = JsonSplitter::default();
         let a = "null";
         let messages  = splitter.push_and_flush(a.as_bytes()).unwrap();
-        assert!(messages.len() > 0);
+        assert!(!messages.is_empty());
         assert_eq!(messages[0], a);
     }
+
+    #[test]
+    fn parse_message_split_across_pushes() {
+        let mut splitter = JsonSplitter::default();
+        assert!(splitter.push(b"{\"a\": [1, ").unwrap().is_empty());
+        assert!(splitter.push(b"2], \"b\": \"}").unwrap().is_empty());
+        let messages = splitter.push(b"\"}\n{}").unwrap();
+        assert_eq!(messages, vec!["{\"a\": [1, 2], \"b\": \"}\"}", "{}"]);
+    }
+
+    #[test]
+    fn parse_root_level_scalars() {
+        let mut splitter = JsonSplitter::default();
+        let messages = splitter.push_and_flush(b"12 true\0-1.5e3[1]false").unwrap();
+        assert_eq!(messages, vec!["12", "true", "-1.5e3", "[1]", "false"]);
+    }
+
+    #[test]
+    fn parse_multi_byte_utf8() {
+        let mut splitter = JsonSplitter::default();
+        let a = "{\"text\": \"grüße 😀\"}".as_bytes();
+        // split in the middle of a multi-byte character
+        let (first, second) = a.split_at(14);
+        assert!(splitter.push(first).unwrap().is_empty());
+        let messages = splitter.push(second).unwrap();
+        assert_eq!(messages, vec!["{\"text\": \"grüße 😀\"}"]);
+    }
+
+    #[test]
+    fn string_ending_in_escaped_backslash() {
+        let mut splitter = JsonSplitter::default();
+        let messages = splitter.push(b"{\"a\": \"x\\\\\"}\"y\\n\"").unwrap();
+        assert_eq!(messages, vec!["{\"a\": \"x\\\\\"}", "\"y\\n\""]);
+    }
+
+    #[test]
+    fn skip_values_exceeding_max_size() {
+        let mut splitter = JsonSplitter::new(8);
+        assert!(splitter.push(b"{\"a\": [1, {\"b\": ").unwrap().is_empty());
+        assert_eq!(splitter.take_skipped(), vec![SplitError::TooLarge(8)]);
+        let messages = splitter.push(b"\"}]\"}]} [1,2,3] {\"c\": 1}").unwrap();
+        assert_eq!(messages, vec!["[1,2,3]", "{\"c\": 1}"]);
+        assert!(splitter.take_skipped().is_empty());
+    }
+
+    #[test]
+    fn keep_values_completed_before_error() {
+        let mut splitter = JsonSplitter::default();
+        assert!(splitter.push(b"{} 1 ]").is_err());
+        splitter.reset();
+        assert_eq!(splitter.push(b"[]").unwrap(), vec!["{}", "1", "[]"]);
+    }
+
+    #[test]
+    fn skip_only_invalid_utf8_values() {
+        let mut splitter = JsonSplitter::default();
+        let messages = splitter.push(b"{\"a\":1} \"\xff\" [1] {\"b\":").unwrap();
+        assert_eq!(messages, vec!["{\"a\":1}", "[1]"]);
+        assert_eq!(splitter.take_skipped(), vec![SplitError::InvalidUtf8]);
+        assert_eq!(splitter.push(b"2}").unwrap(), vec!["{\"b\":2}"]);
+        assert!(splitter.take_skipped().is_empty());
+    }
+
+    #[test]
+    fn flush_inside_message_is_incomplete() {
+        let mut splitter = JsonSplitter::default();
+        splitter.push(b"{\"a\":").unwrap();
+        assert_eq!(splitter.flush(), Err(SplitError::Incomplete));
+    }
 }
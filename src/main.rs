@@ -1,21 +1,96 @@
 #![allow(dead_code)]
 
-use adapters::{tcp::TcpAdapter, test::TestAdapter, ws::WebSocketAdapter};
+use adapters::{proxy::ProxyAdapter, tcp::TcpAdapter, test::TestAdapter, ws::WebSocketAdapter, ws_client::WebSocketClientAdapter};
 use app::App;
+use cli::{Options, USAGE_TEXT};
+use headless::HeadlessUI;
+use recorder::Recorder;
+use tls::Certificate;
+use ui::{Frontend, UI};
 mod adapters;
 mod parser;
 mod app;
 mod ui;
 mod json;
+mod cli;
+mod time;
+mod rules;
+mod template;
+mod headless;
+mod recorder;
+mod replay;
+mod jsonreader;
+mod tls;
+mod binary;
+mod filter;
+mod editor;
+mod history;
+mod mocks;
 
 fn main() -> anyhow::Result<()> {
 
-    let mut app = App::default();
-    app.add(Box::new(WebSocketAdapter::from_addr("127.0.0.1:8080")?));
-    //app.add(Box::new(TcpAdapter::from_addr("127.0.0.1:8080")?));
-    //app.add(Box::new(TestAdapter::default()));
+    let opts = match Options::parse(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", USAGE_TEXT);
+            return Err(e);
+        },
+    };
+
+    if opts.help {
+        println!("{}", USAGE_TEXT);
+        return Ok(());
+    }
+
+    let recorder = match opts.record {
+        Some(ref path) => Some(Recorder::open(path)?),
+        None => None,
+    };
+
+    // bind all adapters before curses takes over the terminal,
+    // so errors are still readable
+    let mut adapters: Vec<Box<dyn adapters::common::Adapter>> = vec![];
+    for addr in opts.ws.iter() {
+        adapters.push(Box::new(WebSocketAdapter::from_addr(addr.as_str(), &opts.handshake)?));
+    }
+    if !opts.wss.is_empty() {
+        let cert = Certificate::from_options(opts.cert.as_deref(), opts.key.as_deref(), opts.self_signed)?;
+        for addr in opts.wss.iter() {
+            adapters.push(Box::new(WebSocketAdapter::from_addr_secure(addr.as_str(), &cert, &opts.handshake)?));
+        }
+    }
+    for url in opts.connect.iter() {
+        adapters.push(Box::new(WebSocketClientAdapter::connect(url)?));
+    }
+    for (addr, url) in opts.proxy.iter() {
+        adapters.push(Box::new(ProxyAdapter::from_addr(addr.as_str(), url)?));
+    }
+    for (addr, framing) in opts.tcp.iter() {
+        adapters.push(Box::new(TcpAdapter::from_addr(addr.as_str(), *framing)?));
+    }
+    if opts.test_adapter {
+        adapters.push(Box::new(TestAdapter::default()));
+    }
+
+    let ui: Box<dyn Frontend> = if opts.headless {
+        Box::new(HeadlessUI::new(opts.output, opts.script.as_deref())?)
+    } else {
+        Box::new(UI::default())
+    };
+
+    let rules_file = opts.rules_file();
+    let mut app = App::new(ui, opts.mocks);
+    if let Some(r) = recorder {
+        app.set_recorder(r);
+    }
+    app.set_routes(opts.routes);
+    if let Some(path) = rules_file {
+        app.set_rules_file(path);
+    }
+    for a in adapters {
+        app.add(a);
+    }
     app.run();
 
     Ok(())
 }
-
@@ -0,0 +1,303 @@
+use std::{collections::BTreeMap, fs, io, path::{Component, Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+use anyhow::anyhow;
+
+use crate::{binary, json::JsonValue, replay::Payload, template};
+
+// how often the directory is checked for changed files
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+// subfolders deeper than this are ignored, which also stops symlink loops
+const MAX_DEPTH: usize = 8;
+
+// modification time and size, to notice changes
+type Stamp = (Option<SystemTime>, u64);
+
+/// Splits the leading `#` lines off a text mock. They describe
+/// the mock in `:ls` and are not sent.
+pub fn split_header(content: &str) -> (Option<String>, &str) {
+    let mut description = vec![];
+    let mut rest = content;
+
+    while let Some(line) = rest.strip_prefix('#') {
+        let (line, tail) = line.split_once('\n').unwrap_or((line, ""));
+        if !line.trim().is_empty() {
+            description.push(line.trim());
+        }
+        rest = tail;
+    }
+
+    let description = (!description.is_empty()).then(|| description.join(" "));
+    (description, rest)
+}
+
+/// Where a new mock called `name` goes below `root`. Only plain relative
+/// names are accepted, and existing files are not overwritten.
+pub fn new_mock_path(root: &Path, name: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(name.trim());
+    if relative.as_os_str().is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(format!("invalid mock name {}, expected a path inside {}", name, root.display()));
+    }
+
+    let path = root.join(relative);
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    Ok(path)
+}
+
+/// A message file of the mock library, loaded and checked when it changes.
+#[derive(Debug)]
+pub struct Mock {
+    pub description: Option<String>,
+    /// none when the file could not be loaded
+    pub payload: Option<Payload>,
+    /// why the mock could not be loaded, or what is wrong with it
+    pub error: Option<String>,
+    stamp: Stamp,
+}
+
+impl Mock {
+    fn load(path: &Path, stamp: Stamp) -> Self {
+        let mut mock = Mock { description: None, payload: None, error: None, stamp };
+
+        // .bin mocks are sent as they are, .hex mocks hold the bytes as hex text
+        let extension = path.extension().and_then(|e| e.to_str());
+        if extension == Some("bin") {
+            match fs::read(path) {
+                Ok(bytes) => mock.payload = Some(Payload::Binary(bytes)),
+                Err(e) => mock.error = Some(e.to_string()),
+            }
+            return mock;
+        }
+
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                mock.error = Some(e.to_string());
+                return mock;
+            },
+        };
+        let (description, body) = split_header(&content);
+        mock.description = description;
+
+        match extension {
+            Some("hex") => match binary::parse_hex(body) {
+                Ok(bytes) => mock.payload = Some(Payload::Binary(bytes)),
+                Err(e) => mock.error = Some(e.to_string()),
+            },
+            _ => {
+                // invalid json mocks are still sent, the error is a warning
+                if extension == Some("json") {
+                    mock.error = Mock::check_json(body).err().map(|e| e.to_string());
+                }
+                mock.payload = Some(Payload::Text(String::from(body)));
+            },
+        }
+
+        mock
+    }
+
+    fn check_json(body: &str) -> anyhow::Result<()> {
+        let stubbed = template::stub(body)?;
+        JsonValue::parse(&stubbed).map_err(|e| anyhow!("invalid json: {}", e))?;
+        Ok(())
+    }
+}
+
+/// All mocks below a directory, by their path relative to it, e.g. `auth/login.json`.
+/// Subfolders group mocks, and `poll` picks up files changed on disk.
+#[derive(Debug)]
+pub struct MockLibrary {
+    pub root: PathBuf,
+    pub mocks: BTreeMap<String, Mock>,
+    last_scan: Option<Instant>,
+    // reported once, not on every scan
+    error: Option<String>,
+}
+
+impl MockLibrary {
+    pub fn new(root: PathBuf) -> Self {
+        MockLibrary { root, mocks: BTreeMap::new(), last_scan: None, error: None }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Mock> {
+        self.mocks.get(name.trim().trim_start_matches("./"))
+    }
+
+    /// Scans the directory if the last scan is long enough ago.
+    pub fn poll(&mut self) -> Vec<String> {
+        match self.last_scan {
+            Some(t) if t.elapsed() < SCAN_INTERVAL => vec![],
+            _ => self.scan(),
+        }
+    }
+
+    /// Loads new and changed files and forgets removed ones.
+    /// Returns what changed and the problems found, for the log.
+    pub fn scan(&mut self) -> Vec<String> {
+        let initial = self.last_scan.is_none();
+        self.last_scan = Some(Instant::now());
+
+        let mut report = vec![];
+        let mut found = vec![];
+        match MockLibrary::walk(&self.root, "", 0, &mut found) {
+            Ok(_) => self.error = None,
+            Err(e) => {
+                let msg = format!("could not read mock directory {}: {}", self.root.display(), e);
+                if self.error.as_ref() != Some(&msg) {
+                    report.push(msg.clone());
+                }
+                self.error = Some(msg);
+            },
+        }
+
+        let removed: Vec<String> = self.mocks.keys()
+            .filter(|k| !found.iter().any(|(name, _, _)| name == *k))
+            .cloned()
+            .collect();
+        for name in removed {
+            self.mocks.remove(&name);
+            report.push(format!("mock removed: {}", name));
+        }
+
+        for (name, path, stamp) in found {
+            let verb = match self.mocks.get(&name) {
+                Some(m) if m.stamp == stamp => continue,
+                Some(_) => "changed",
+                None => "added",
+            };
+
+            let mock = Mock::load(&path, stamp);
+            if !initial {
+                report.push(format!("mock {}: {}", verb, name));
+            }
+            if let Some(ref e) = mock.error {
+                report.push(format!("mock {} has a problem: {}", name, e));
+            }
+            self.mocks.insert(name, mock);
+        }
+
+        if initial && self.error.is_none() {
+            report.insert(0, format!("found {} mocks in {}", self.mocks.len(), self.root.display()));
+        }
+        report
+    }
+
+    fn walk(dir: &Path, prefix: &str, depth: usize, found: &mut Vec<(String, PathBuf, Stamp)>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            // hidden files and editor backups
+            if file_name.starts_with('.') || file_name.ends_with('~') {
+                continue;
+            }
+
+            let path = entry.path();
+            let name = format!("{}{}", prefix, file_name);
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+
+            if meta.is_dir() {
+                if depth < MAX_DEPTH {
+                    if let Err(e) = MockLibrary::walk(&path, &format!("{}/", name), depth + 1, found) {
+                        eprintln!("could not read {}: {}", path.display(), e);
+                    }
+                }
+            } else {
+                found.push((name, path, (meta.modified().ok(), meta.len())));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rows for `:ls`, with the mocks of each subfolder under its name.
+    pub fn listing(&self) -> Vec<String> {
+        let width = self.mocks.keys()
+            .map(|n| n.rsplit('/').next().unwrap_or(n).len())
+            .max()
+            .unwrap_or(0);
+
+        // files at the top first, then each folder
+        let mut entries: Vec<(&str, &str, &Mock)> = self.mocks.iter()
+            .map(|(name, mock)| match name.rsplit_once('/') {
+                Some((d, f)) => (d, f, mock),
+                None => ("", name.as_str(), mock),
+            })
+            .collect();
+        entries.sort_by_key(|(d, f, _)| (*d, *f));
+
+        let mut rows = vec![];
+        let mut folder = "";
+        for (dir, file, mock) in entries {
+            if dir != folder {
+                rows.push(format!("{}/", dir));
+                folder = dir;
+            }
+
+            let indent = if dir.is_empty() { "" } else { "  " };
+            let mut row = format!("{}{:<width$}", indent, file, width = width);
+            if let Some(ref e) = mock.error {
+                row.push_str(&format!("  [{}]", e));
+            }
+            if let Some(ref d) = mock.description {
+                row.push_str(&format!("  {}", d));
+            }
+            rows.push(row.trim_end().to_string());
+        }
+
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_description_header() {
+        assert_eq!(split_header("# login as admin\n#\n# role: admin\n{}"), (Some(String::from("login as admin role: admin")), "{}"));
+        assert_eq!(split_header("{\"a\": \"#\"}"), (None, "{\"a\": \"#\"}"));
+    }
+
+    #[test]
+    fn accept_only_new_paths_inside_root() {
+        let root = std::env::temp_dir().join(format!("termws-new-mock-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("taken.json"), "{}").unwrap();
+
+        assert_eq!(new_mock_path(&root, "auth/login.json"), Ok(root.join("auth/login.json")));
+        assert!(new_mock_path(&root, "./x.json").is_ok());
+        assert!(new_mock_path(&root, "taken.json").unwrap_err().ends_with("already exists"));
+        for name in ["../x.json", "auth/../../x.json", "/tmp/x.json", ""] {
+            assert!(new_mock_path(&root, name).unwrap_err().starts_with("invalid mock name"), "{}", name);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn index_nested_folders_and_changes() {
+        let root = std::env::temp_dir().join(format!("termws-mocks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("auth")).unwrap();
+        fs::write(root.join("ping.hex"), "# a ping\n01 02").unwrap();
+        fs::write(root.join("auth/login.json"), "{ \"id\": \"{{uuid}}\" }").unwrap();
+        fs::write(root.join("auth/.login.json.swp"), "").unwrap();
+
+        let mut lib = MockLibrary::new(root.clone());
+        assert_eq!(lib.scan(), vec![format!("found 2 mocks in {}", root.display())]);
+        assert_eq!(lib.get("ping.hex").unwrap().payload, Some(Payload::Binary(vec![1, 2])));
+        assert_eq!(lib.listing(), vec!["ping.hex    a ping", "auth/", "  login.json"]);
+
+        fs::write(root.join("auth/login.json"), "{ \"id\": }").unwrap();
+        fs::remove_file(root.join("ping.hex")).unwrap();
+        let report = lib.scan();
+        assert_eq!(report[0], "mock removed: ping.hex");
+        assert_eq!(report[1], "mock changed: auth/login.json");
+        assert!(report[2].starts_with("mock auth/login.json has a problem: invalid json"));
+        assert!(lib.get("./auth/login.json").unwrap().payload.is_some());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,17 +1,105 @@
+use crate::replay;
+
 pub struct Parser;
 
+const CLOSE_NORMAL: u16 = 1000;
+
+/// Command names offered by Tab completion, without their short forms.
+pub const COMMANDS: &[&str] = &[
+    "ls", "clients", "rules", "exit", "help", "send", "edit", "replay",
+    "drop", "hold", "release", "unfilter", "filter", "ping", "close",
+];
+
 pub const HELP_TEXT: &str = r"
 Allowed commands:
 
-:ls                  - List all available json messages
+:ls                  - List all available mocks by folder, with their descriptions and problems.
+                       Leading # lines of a mock describe it and are not sent.
+                       Files changed in the mocks directory are picked up automatically
+:clients             - List all connected clients
+:rules               - Reload and list the auto-responder rules
 :exit                - End program
 :help, :h            - Print help text
-:send, :s <file>     - Send json message. <file> must be one of the files listed with :ls
+:send, :s <file>     - Send json message. <file> must be one of the files listed with :ls,
+                       with its folder, e.g. :send auth/login.json.
+                       .bin files are sent as binary frames, as are .hex files holding hex text
+:send @<client> <file>
+                     - Send json message to a single client only, e.g. :send @ws#3 test.json.
+                       <file> is taken from the --route directory matching the client's path.
+                       With --proxy, :send @up#4 injects the message towards the server
+:edit [@<client>] [file] [> <name>]
+                     - Open <file>, or else the last message received from <client> or anyone,
+                       in $EDITOR and send the result to all clients or only <client>.
+                       With > <name> it is saved to the mocks directory as well
+:replay <file> [speed]
+                     - Re-send the outgoing messages of a recorded session to all clients,
+                       with the original timing divided by speed (default: 1, at most 1000)
+:replay pause|resume|step|stop
+                     - Control a running replay. step sends the next message right away
+:drop <regex>        - Proxy: drop messages matching <regex> instead of forwarding them
+:hold <regex>        - Proxy: hold back messages matching <regex>
+:release             - Proxy: forward all held messages
+:unfilter            - Proxy: remove all drop and hold filters
+:filter [conditions]
+                     - Show only lines matching all conditions, or all lines again without any:
+                       in, out, @<client>, <field>=<value>, /<json pointer>=<value>, and a
+                       regex spanning the rest, e.g. :filter in @ws#3 type=error
+:ping [@<client>]    - Ping all WebSocket clients, or a single one, and show the round trip time.
+                       With --proxy, :ping @up#4 pings the server side
+:close [@<client>] [code] [reason]
+                     - Close all WebSocket connections, or a single one, with a status code
+                       (default: 1000) and reason, e.g. :close @ws#3 4001 session expired.
+                       With --proxy, both ends of a client's connection are closed
+
+Keys:
+
+j, k                 - Move the selection down, up
+g, G                 - Jump to start, to end and follow new lines
+Enter                - Show the selected line in full with its metadata. Esc closes it
+/<regex>             - Search and highlight matches. An empty search removes the highlighting
+n, N                 - Jump to next, previous match
+p                    - Toggle pretty printing of json messages and hex dumps of binary messages
+x                    - Switch binary messages between hex and base64
+
+While typing a command or search:
+
+Left, Right, Home, End
+                     - Move the cursor
+Up, Down             - Browse earlier commands and searches, kept in ~/.termws_history
+Tab                  - Complete command names and the mock files of :send and :edit.
+                       Pressing it again cycles through the matches
 ";
 
+#[derive(Debug, PartialEq)]
+pub enum ReplayCommand {
+    Start(String, f64),
+    Pause,
+    Resume,
+    Step,
+    Stop,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProxyCommand {
+    Drop(String),
+    Hold(String),
+    Release,
+    Clear,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum ParseResult {
-    Send(String),
+    Send(Option<String>, String),
+    /// target, mock to start from, and name to save the result as
+    Edit(Option<String>, Option<String>, Option<String>),
     List,
+    Clients,
+    Rules,
+    Replay(ReplayCommand),
+    Proxy(ProxyCommand),
+    Filter(String),
+    Ping(Option<String>),
+    Close(Option<String>, u16, String),
     Help,
     Exit,
     Malformed(String),
@@ -23,7 +111,7 @@ impl Parser {
         if let Some((_, r)) = s.split_once(':') {
             s = r;
         } else {
-            ParseResult::Malformed(command.clone());
+            return ParseResult::Malformed(command.clone());
         }
 
         let cmd: &str;
@@ -38,11 +126,219 @@ impl Parser {
 
         match cmd {
             "ls" => ParseResult::List,
+            "clients" => ParseResult::Clients,
+            "rules" => ParseResult::Rules,
             "exit" => ParseResult::Exit,
             "help" | "h" => ParseResult::Help,
-            "send" | "s" => ParseResult::Send(String::from(rest)),
+            "send" | "s" => Parser::parse_send(rest),
+            "edit" | "e" => Parser::parse_edit(rest),
+            "replay" => Parser::parse_replay(rest),
+            "drop" | "hold" => Parser::parse_filter(cmd, rest),
+            "release" => ParseResult::Proxy(ProxyCommand::Release),
+            "unfilter" => ParseResult::Proxy(ProxyCommand::Clear),
+            "filter" => ParseResult::Filter(String::from(rest.trim())),
+            "ping" => Parser::parse_ping(rest),
+            "close" => Parser::parse_close(rest),
             _ => ParseResult::Malformed(format!("could not parse {}", s)),
         }
     }
+
+    /// Candidates for the word ending at the end of `input`: a command name,
+    /// or a mock for `:send` and `:edit`. Returns where that word starts.
+    pub fn complete(input: &str, mocks: &[String]) -> (usize, Vec<String>) {
+        let Some(s) = input.strip_prefix(':') else {
+            return (input.len(), vec![]);
+        };
+
+        let start = input.rfind(' ').map(|i| i + 1).unwrap_or(1);
+        let word = &input[start..];
+        let candidates: Vec<&str> = match s.split_once(' ') {
+            None => COMMANDS.to_vec(),
+            Some((cmd, rest)) => {
+                // the mock is the first argument after the optional @<client>
+                let (_, args) = Parser::parse_target(rest);
+                let is_file = !word.starts_with('@') && !args.contains(' ') && !args.contains('>');
+                match cmd {
+                    "send" | "s" | "edit" | "e" if is_file => mocks.iter().map(|m| m.as_str()).collect(),
+                    _ => vec![],
+                }
+            },
+        };
+
+        let matches = candidates.into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(String::from)
+            .collect();
+        (start, matches)
+    }
+
+    fn parse_replay(rest: &str) -> ParseResult {
+        let mut args = rest.split_whitespace();
+        let cmd = match (args.next(), args.next()) {
+            (Some("pause"), None) => ReplayCommand::Pause,
+            (Some("resume"), None) => ReplayCommand::Resume,
+            (Some("step"), None) => ReplayCommand::Step,
+            (Some("stop"), None) => ReplayCommand::Stop,
+            (Some(file), None) => ReplayCommand::Start(String::from(file), 1.0),
+            (Some(file), Some(speed)) => {
+                match speed.parse::<f64>() {
+                    Ok(speed) if replay::is_valid_speed(speed) => ReplayCommand::Start(String::from(file), speed),
+                    _ => return ParseResult::Malformed(format!("invalid replay speed: {}", speed)),
+                }
+            },
+            (None, _) => return ParseResult::Malformed(String::from("missing file to replay")),
+        };
+
+        ParseResult::Replay(cmd)
+    }
+
+    fn parse_filter(cmd: &str, rest: &str) -> ParseResult {
+        let pattern = String::from(rest.trim());
+        if pattern.is_empty() {
+            return ParseResult::Malformed(format!("missing pattern for {}", cmd));
+        }
+
+        match cmd {
+            "drop" => ParseResult::Proxy(ProxyCommand::Drop(pattern)),
+            _ => ParseResult::Proxy(ProxyCommand::Hold(pattern)),
+        }
+    }
+
+    /// Splits off a leading `@<client>`.
+    fn parse_target(rest: &str) -> (Option<String>, &str) {
+        let rest = rest.trim();
+        match rest.strip_prefix('@') {
+            Some(r) => {
+                let (target, rest) = r.split_once(' ').unwrap_or((r, ""));
+                (Some(String::from(target)), rest.trim())
+            },
+            None => (None, rest),
+        }
+    }
+
+    fn parse_ping(rest: &str) -> ParseResult {
+        match Parser::parse_target(rest) {
+            (target, "") => ParseResult::Ping(target),
+            (_, r) => ParseResult::Malformed(format!("unexpected arguments for ping: {}", r)),
+        }
+    }
+
+    fn parse_close(rest: &str) -> ParseResult {
+        let (target, rest) = Parser::parse_target(rest);
+        if rest.is_empty() {
+            return ParseResult::Close(target, CLOSE_NORMAL, String::new());
+        }
+
+        let (code, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+        match code.parse::<u16>() {
+            // 1005, 1006 and 1015 are reserved for reporting and never sent
+            Ok(c) if (CLOSE_NORMAL..=4999).contains(&c) && ![1005, 1006, 1015].contains(&c) => {
+                ParseResult::Close(target, c, String::from(reason.trim()))
+            },
+            _ => ParseResult::Malformed(format!("invalid close code: {}", code)),
+        }
+    }
+
+    fn parse_edit(rest: &str) -> ParseResult {
+        let (target, rest) = Parser::parse_target(rest);
+        let (file, save_as) = match rest.split_once('>') {
+            Some((file, name)) => (file.trim(), Some(name.trim())),
+            None => (rest, None),
+        };
+        if save_as == Some("") {
+            return ParseResult::Malformed(String::from("missing name to save as"));
+        }
+
+        let file = (!file.is_empty()).then(|| String::from(file));
+        ParseResult::Edit(target, file, save_as.map(String::from))
+    }
+
+    fn parse_send(rest: &str) -> ParseResult {
+        match Parser::parse_target(rest) {
+            (Some(target), "") => ParseResult::Malformed(format!("missing file for target {}", target)),
+            (target, file) => ParseResult::Send(target, String::from(file)),
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_send_to_all() {
+        let r = Parser::parse(String::from(":send test.json"));
+        assert_eq!(r, ParseResult::Send(None, String::from("test.json")));
+    }
+
+    #[test]
+    fn parse_send_to_target() {
+        let r = Parser::parse(String::from(":s @ws#3 test.json"));
+        assert_eq!(r, ParseResult::Send(Some(String::from("ws#3")), String::from("test.json")));
+    }
+
+    #[test]
+    fn parse_send_to_target_without_file() {
+        let r = Parser::parse(String::from(":send @ws#3"));
+        assert!(matches!(r, ParseResult::Malformed(_)));
+    }
+
+    #[test]
+    fn parse_replay() {
+        let r = Parser::parse(String::from(":replay session.jsonl 2.5"));
+        assert_eq!(r, ParseResult::Replay(ReplayCommand::Start(String::from("session.jsonl"), 2.5)));
+        let r = Parser::parse(String::from(":replay step"));
+        assert_eq!(r, ParseResult::Replay(ReplayCommand::Step));
+        assert!(matches!(Parser::parse(String::from(":replay a.jsonl 0")), ParseResult::Malformed(_)));
+        assert!(matches!(Parser::parse(String::from(":replay a.jsonl inf")), ParseResult::Malformed(_)));
+        assert!(matches!(Parser::parse(String::from(":replay a.jsonl 1e300")), ParseResult::Malformed(_)));
+        assert!(matches!(Parser::parse(String::from(":replay")), ParseResult::Malformed(_)));
+    }
+
+    #[test]
+    fn parse_proxy_filters() {
+        let r = Parser::parse(String::from(":drop \"type\": *\"ping\""));
+        assert_eq!(r, ParseResult::Proxy(ProxyCommand::Drop(String::from("\"type\": *\"ping\""))));
+        assert_eq!(Parser::parse(String::from(":release")), ParseResult::Proxy(ProxyCommand::Release));
+        assert!(matches!(Parser::parse(String::from(":hold")), ParseResult::Malformed(_)));
+    }
+
+    #[test]
+    fn parse_edit() {
+        assert_eq!(Parser::parse(String::from(":edit")), ParseResult::Edit(None, None, None));
+        let r = Parser::parse(String::from(":e @ws#2 test.json > test2.json"));
+        assert_eq!(r, ParseResult::Edit(Some(String::from("ws#2")), Some(String::from("test.json")), Some(String::from("test2.json"))));
+        assert_eq!(Parser::parse(String::from(":edit > new.json")), ParseResult::Edit(None, None, Some(String::from("new.json"))));
+        assert!(matches!(Parser::parse(String::from(":edit test.json >")), ParseResult::Malformed(_)));
+    }
+
+    #[test]
+    fn parse_ping_and_close() {
+        assert_eq!(Parser::parse(String::from(":ping")), ParseResult::Ping(None));
+        assert_eq!(Parser::parse(String::from(":ping @ws#2")), ParseResult::Ping(Some(String::from("ws#2"))));
+        assert_eq!(Parser::parse(String::from(":close")), ParseResult::Close(None, 1000, String::new()));
+        let r = Parser::parse(String::from(":close @ws#3 4001 session expired"));
+        assert_eq!(r, ParseResult::Close(Some(String::from("ws#3")), 4001, String::from("session expired")));
+        assert!(matches!(Parser::parse(String::from(":close 1006")), ParseResult::Malformed(_)));
+        assert!(matches!(Parser::parse(String::from(":close @ws#3 abc")), ParseResult::Malformed(_)));
+    }
+
+    #[test]
+    fn complete_commands_and_mocks() {
+        let mocks = vec![String::from("login.json"), String::from("logout.json"), String::from("ping.hex")];
+        assert_eq!(Parser::complete(":re", &mocks), (1, vec![String::from("replay"), String::from("release")]));
+        assert_eq!(Parser::complete(":send lo", &mocks), (6, vec![String::from("login.json"), String::from("logout.json")]));
+        assert_eq!(Parser::complete(":s @ws#1 p", &mocks), (9, vec![String::from("ping.hex")]));
+        assert_eq!(Parser::complete(":edit ping.hex > p", &mocks).1, Vec::<String>::new());
+        assert_eq!(Parser::complete(":filter p", &mocks).1, Vec::<String>::new());
+        assert_eq!(Parser::complete("/p", &mocks).1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn parse_all_commands() {
+        for c in COMMANDS {
+            let r = Parser::parse(format!(":{}", c));
+            assert!(!matches!(r, ParseResult::Malformed(ref m) if m.starts_with("could not parse")), "{}", c);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use std::{fs::{File, OpenOptions}, io::Write, path::Path};
+
+use crate::adapters::common::Line;
+
+/// Appends every line to a traffic log as JSON Lines, one object per line:
+///
+/// `{"time":"2024-05-01T13:37:00.042Z","dir":"in","conn":"ws#1","json":true,"invalid_json":false,"text":"...","raw":"..."}`
+///
+/// Log lines carry no `dir` and no `raw` payload.
+#[derive(Debug)]
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        Ok(Recorder { file })
+    }
+
+    pub fn record(&mut self, line: &Line) -> anyhow::Result<()> {
+        // written in one call, so an interrupted session
+        // leaves at most one truncated record
+        let record = format!("{}\n", line.to_json());
+        self.file.write_all(record.as_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::{collections::VecDeque, fs, path::Path, time::{Duration, Instant, SystemTime}};
+use anyhow::anyhow;
+
+use crate::{binary, json::JsonValue, time};
+
+// faster replays would only send everything at once, and
+// scaling elapsed time by huge factors overflows `Duration`
+const MAX_SPEED: f64 = 1000.0;
+
+/// Whether `speed` is a usable replay speed, above 0 and at most 1000.
+pub fn is_valid_speed(speed: f64) -> bool {
+    speed.is_finite() && speed > 0.0 && speed <= MAX_SPEED
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ReplayMessage {
+    /// time since the first recorded outgoing message
+    pub offset: Duration,
+    pub payload: Payload,
+}
+
+/// Re-sends the outgoing messages of a traffic log written by `Recorder`,
+/// keeping their original spacing scaled by `speed`.
+#[derive(Debug)]
+pub struct Replay {
+    messages: VecDeque<ReplayMessage>,
+    total: usize,
+    speed: f64,
+    // replay time reached before the current run, and when that run started
+    elapsed: Duration,
+    resumed_at: Option<Instant>,
+}
+
+impl Replay {
+    pub fn load(path: &Path, speed: f64) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Replay::parse(&content, speed)
+    }
+
+    pub fn parse(content: &str, speed: f64) -> anyhow::Result<Self> {
+        if !is_valid_speed(speed) {
+            return Err(anyhow!("speed must be above 0 and at most {}", MAX_SPEED));
+        }
+
+        let mut start: Option<SystemTime> = None;
+        let mut messages = VecDeque::new();
+
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record = JsonValue::parse(line)
+                .map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+            if record.get("dir").and_then(|d| d.as_str()) != Some("out") {
+                continue;
+            }
+            // pings and closes are not replayed
+            if matches!(record.get("control"), Some(JsonValue::Bool(true))) {
+                continue;
+            }
+
+            let timestamp = record.get("time")
+                .and_then(|t| t.as_str())
+                .and_then(time::parse_iso8601)
+                .ok_or_else(|| anyhow!("line {}: missing or invalid time", i + 1))?;
+            let payload = match record.get("binary").and_then(|b| b.as_str()) {
+                Some(b) => Payload::Binary(binary::base64_decode(b).map_err(|e| anyhow!("line {}: {}", i + 1, e))?),
+                None => {
+                    let text = record.get("raw")
+                        .or_else(|| record.get("text"))
+                        .and_then(|p| p.as_str())
+                        .ok_or_else(|| anyhow!("line {}: missing payload", i + 1))?;
+                    Payload::Text(String::from(text))
+                },
+            };
+
+            let start = *start.get_or_insert(timestamp);
+            messages.push_back(ReplayMessage {
+                offset: timestamp.duration_since(start).unwrap_or(Duration::ZERO),
+                payload,
+            });
+        }
+
+        Ok(Replay {
+            total: messages.len(),
+            messages,
+            speed,
+            elapsed: Duration::ZERO,
+            resumed_at: Some(Instant::now()),
+        })
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn sent(&self) -> usize {
+        self.total - self.messages.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.resumed_at.is_none()
+    }
+
+    fn position(&self, now: Instant) -> Duration {
+        match self.resumed_at {
+            Some(t) => self.elapsed + now.duration_since(t).mul_f64(self.speed),
+            None => self.elapsed,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.elapsed = self.position(Instant::now());
+        self.resumed_at = None;
+    }
+
+    pub fn resume(&mut self) {
+        if self.is_paused() {
+            self.resumed_at = Some(Instant::now());
+        }
+    }
+
+    /// Messages that are due at `now`.
+    pub fn due(&mut self, now: Instant) -> Vec<ReplayMessage> {
+        let position = self.position(now);
+        let mut r = vec![];
+
+        while self.messages.front().is_some_and(|m| m.offset <= position) {
+            r.extend(self.messages.pop_front());
+        }
+
+        r
+    }
+
+    /// Pauses the replay and returns the next message right away.
+    pub fn step(&mut self) -> Option<ReplayMessage> {
+        self.pause();
+        let m = self.messages.pop_front()?;
+        self.elapsed = self.elapsed.max(m.offset);
+        Some(m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"
+{"time":"2024-05-01T13:37:00.000Z","text":"client connected"}
+{"time":"2024-05-01T13:37:00.500Z","dir":"in","conn":"ws#1","json":true,"invalid_json":false,"text":"{\"a\":1}","raw":"{ \"a\": 1 }"}
+{"time":"2024-05-01T13:37:01.000Z","dir":"out","conn":"ws#1","json":true,"invalid_json":false,"text":"{\"b\":1}","raw":"{ \"b\": 1 }"}
+{"time":"2024-05-01T13:37:03.000Z","dir":"out","conn":"ws#1","json":true,"invalid_json":false,"text":"{\"b\":2}"}
+"#;
+
+    #[test]
+    fn load_outgoing_messages_only() {
+        let replay = Replay::parse(LOG, 1.0).unwrap();
+        assert_eq!(replay.total(), 2);
+        assert_eq!(replay.messages[0], ReplayMessage { offset: Duration::ZERO, payload: Payload::Text(String::from("{ \"b\": 1 }")) });
+        assert_eq!(replay.messages[1], ReplayMessage { offset: Duration::from_secs(2), payload: Payload::Text(String::from("{\"b\":2}")) });
+    }
+
+    #[test]
+    fn scale_timing_by_speed() {
+        let mut replay = Replay::parse(LOG, 4.0).unwrap();
+        let start = replay.resumed_at.unwrap();
+        assert_eq!(replay.due(start).len(), 1);
+        assert!(replay.due(start + Duration::from_millis(400)).is_empty());
+        assert_eq!(replay.due(start + Duration::from_millis(500)).len(), 1);
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn step_while_paused() {
+        let mut replay = Replay::parse(LOG, 1.0).unwrap();
+        replay.pause();
+        assert!(replay.step().is_some());
+        assert!(replay.is_paused());
+        assert!(replay.due(Instant::now() + Duration::from_secs(10)).is_empty());
+        assert!(replay.step().is_some());
+        assert!(replay.step().is_none());
+        assert_eq!(replay.sent(), 2);
+    }
+
+    #[test]
+    fn parse_binary_messages() {
+        let log = r#"{"time":"2024-05-01T13:37:04.000Z","dir":"out","json":false,"invalid_json":false,"text":"binary, 3 bytes","binary":"AQID"}"#;
+        let replay = Replay::parse(log, 1.0).unwrap();
+        assert_eq!(replay.messages[0].payload, Payload::Binary(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn reject_invalid_speed_and_records() {
+        assert!(Replay::parse(LOG, 0.0).is_err());
+        assert!(Replay::parse(LOG, f64::INFINITY).is_err());
+        assert!(Replay::parse(LOG, 1e300).is_err());
+        assert!(Replay::parse("{\"dir\":\"out\",\"text\":\"x\"}", 1.0).is_err());
+        assert!(Replay::parse("not json", 1.0).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fs, path::Path, time::Duration};
+use anyhow::anyhow;
+use regex::Regex;
+
+use crate::json::JsonValue;
+
+/// Decides whether an incoming message triggers a rule.
+#[derive(Debug)]
+pub enum Matcher {
+    /// top-level field of an object equals a value
+    Field(String, JsonValue),
+    /// value at a JSON pointer equals a value, or merely exists
+    Pointer(String, Option<JsonValue>),
+    /// the raw message matches a regular expression
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub fn matches(&self, text: &str, json: Option<&JsonValue>) -> bool {
+        match self {
+            Matcher::Field(key, expected) => {
+                json.and_then(|v| v.get(key)) == Some(expected)
+            },
+            Matcher::Pointer(pointer, expected) => {
+                match (json.and_then(|v| v.pointer(pointer)), expected) {
+                    (Some(v), Some(expected)) => v == expected,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+            },
+            Matcher::Regex(re) => re.is_match(text),
+        }
+    }
+}
+
+impl std::fmt::Display for Matcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Matcher::Field(key, v) => write!(f, "field {} == {}", key, v),
+            Matcher::Pointer(pointer, Some(v)) => write!(f, "pointer {} == {}", pointer, v),
+            Matcher::Pointer(pointer, None) => write!(f, "pointer {} exists", pointer),
+            Matcher::Regex(re) => write!(f, "regex {}", re),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub matcher: Matcher,
+    /// mock file to send back, relative to the mocks directory
+    pub mock: String,
+    pub delay: Duration,
+}
+
+impl Rule {
+    fn from_json(v: &JsonValue) -> anyhow::Result<Self> {
+        let mock = v.get("send")
+            .and_then(|m| m.as_str())
+            .ok_or_else(|| anyhow!("rule is missing \"send\""))?;
+
+        let expected = v.get("equals").cloned();
+        let matcher = if let Some(key) = v.get("field") {
+            let key = key.as_str().ok_or_else(|| anyhow!("\"field\" must be a string"))?;
+            let expected = expected.ok_or_else(|| anyhow!("\"field\" requires \"equals\""))?;
+            Matcher::Field(String::from(key), expected)
+        } else if let Some(pointer) = v.get("pointer") {
+            let pointer = pointer.as_str().ok_or_else(|| anyhow!("\"pointer\" must be a string"))?;
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                return Err(anyhow!("\"pointer\" must start with '/': {}", pointer));
+            }
+            Matcher::Pointer(String::from(pointer), expected)
+        } else if let Some(re) = v.get("regex") {
+            let re = re.as_str().ok_or_else(|| anyhow!("\"regex\" must be a string"))?;
+            Matcher::Regex(Regex::new(re)?)
+        } else {
+            return Err(anyhow!("rule needs one of \"field\", \"pointer\" or \"regex\""));
+        };
+
+        let delay = match v.get("delay") {
+            None => Duration::ZERO,
+            Some(JsonValue::Number(ms)) if *ms >= 0.0 => Duration::from_millis(*ms as u64),
+            Some(_) => return Err(anyhow!("\"delay\" must be a non-negative number of milliseconds")),
+        };
+
+        Ok(Rule {
+            matcher,
+            mock: String::from(mock),
+            delay,
+        })
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.matcher, self.mock)?;
+        if !self.delay.is_zero() {
+            write!(f, " after {}ms", self.delay.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+/// Auto-responder rules, loaded from a json file such as
+///
+/// ```json
+/// [
+///     { "field": "message", "equals": "opened", "send": "welcome.json" },
+///     { "pointer": "/user/id", "equals": 42, "send": "user.json", "delay": 500 },
+///     { "regex": "\"iteration\":\\s*\\d+", "send": "ack.json" }
+/// ]
+/// ```
+#[derive(Debug, Default)]
+pub struct Rules {
+    pub rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Rules::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let v = JsonValue::parse(content)?;
+        let JsonValue::Array(items) = v else {
+            return Err(anyhow!("rules file must contain an array of rules"));
+        };
+
+        let mut rules = vec![];
+        for (i, item) in items.iter().enumerate() {
+            let rule = Rule::from_json(item)
+                .map_err(|e| anyhow!("rule {}: {}", i, e))?;
+            rules.push(rule);
+        }
+
+        Ok(Rules { rules })
+    }
+
+    /// All rules triggered by an incoming message.
+    pub fn matching(&self, text: &str) -> Vec<&Rule> {
+        let json = JsonValue::parse(text).ok();
+        self.rules.iter()
+            .filter(|r| r.matcher.matches(text, json.as_ref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"[
+        { "field": "message", "equals": "opened", "send": "welcome.json" },
+        { "pointer": "/user/id", "equals": 42, "send": "user.json", "delay": 500 },
+        { "pointer": "/token", "send": "auth.json" },
+        { "regex": "\"iteration\":\\s*\\d+", "send": "ack.json" }
+    ]"#;
+
+    fn mocks(rules: &Rules, text: &str) -> Vec<String> {
+        rules.matching(text).iter().map(|r| r.mock.clone()).collect()
+    }
+
+    #[test]
+    fn match_field_value() {
+        let rules = Rules::parse(RULES).unwrap();
+        assert_eq!(mocks(&rules, "{ \"message\": \"opened\" }"), vec!["welcome.json"]);
+        assert!(mocks(&rules, "{ \"message\": \"closed\" }").is_empty());
+    }
+
+    #[test]
+    fn match_pointer() {
+        let rules = Rules::parse(RULES).unwrap();
+        assert_eq!(mocks(&rules, "{ \"user\": { \"id\": 42 } }"), vec!["user.json"]);
+        assert_eq!(mocks(&rules, "{ \"token\": null }"), vec!["auth.json"]);
+        assert_eq!(rules.rules[1].delay, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn match_regex() {
+        let rules = Rules::parse(RULES).unwrap();
+        assert_eq!(mocks(&rules, "{ \"message\": \"test\", \"iteration\": 3 }"), vec!["ack.json"]);
+    }
+
+    #[test]
+    fn reject_incomplete_rules() {
+        assert!(Rules::parse("{}").is_err());
+        assert!(Rules::parse("[{ \"field\": \"message\", \"send\": \"a.json\" }]").is_err());
+        assert!(Rules::parse("[{ \"regex\": \"(\", \"send\": \"a.json\" }]").is_err());
+        assert!(Rules::parse("[{ \"pointer\": \"/a\" }]").is_err());
+    }
+}
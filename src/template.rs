@@ -0,0 +1,164 @@
+use std::time::SystemTime;
+use anyhow::anyhow;
+use rand::Rng;
+
+use crate::{json::{escape_string, JsonValue}, time};
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+/// Values available to placeholders while expanding a mock.
+#[derive(Debug, Default)]
+pub struct TemplateContext<'a> {
+    /// increments once per expanded message
+    pub counter: u64,
+    /// last message received on the connection the mock is sent to
+    pub request: Option<&'a JsonValue>,
+}
+
+/// Expands placeholders in a mock file:
+///
+/// `{{now}}`                 - current time as ISO 8601 timestamp
+/// `{{uuid}}`                - random version 4 UUID
+/// `{{counter}}`             - number of messages expanded so far
+/// `{{random_int <a> <b>}}`  - random integer between a and b, inclusive
+/// `{{request.<path>}}`      - field of the last incoming message, e.g. `{{request.user.id}}`
+pub fn expand(text: &str, ctx: &TemplateContext) -> anyhow::Result<String> {
+    replace_placeholders(text, |p| expand_placeholder(p, ctx))
+}
+
+/// Checks all placeholders without a message to take values from, and
+/// replaces each with `0`, so the result of a json mock is valid json.
+pub fn stub(text: &str) -> anyhow::Result<String> {
+    replace_placeholders(text, |p| {
+        if !p.starts_with("request.") {
+            expand_placeholder(p, &TemplateContext::default())?;
+        }
+        Ok(String::from("0"))
+    })
+}
+
+fn replace_placeholders(text: &str, mut f: impl FnMut(&str) -> anyhow::Result<String>) -> anyhow::Result<String> {
+    let mut r = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(OPEN) {
+        r.push_str(&rest[..start]);
+        let after = &rest[start + OPEN.len()..];
+        let end = after.find(CLOSE)
+            .ok_or_else(|| anyhow!("unterminated placeholder: {}", &rest[start..]))?;
+
+        r.push_str(&f(after[..end].trim())?);
+        rest = &after[end + CLOSE.len()..];
+    }
+
+    r.push_str(rest);
+    Ok(r)
+}
+
+fn expand_placeholder(placeholder: &str, ctx: &TemplateContext) -> anyhow::Result<String> {
+    let mut args = placeholder.split_whitespace();
+    let name = args.next().unwrap_or("");
+
+    match name {
+        "now" => Ok(time::format_iso8601(SystemTime::now())),
+        "uuid" => Ok(uuid_v4()),
+        "counter" => Ok(ctx.counter.to_string()),
+        "random_int" => {
+            let (Some(low), Some(high)) = (args.next(), args.next()) else {
+                return Err(anyhow!("random_int needs two bounds: {{{{{}}}}}", placeholder));
+            };
+            let low: i64 = low.parse()?;
+            let high: i64 = high.parse()?;
+            if low > high {
+                return Err(anyhow!("empty range in {{{{{}}}}}", placeholder));
+            }
+            let end = high.checked_add(1)
+                .ok_or_else(|| anyhow!("upper bound too large in {{{{{}}}}}", placeholder))?;
+            Ok(rand::thread_rng().gen_range(low, end).to_string())
+        },
+        _ => {
+            let path = name.strip_prefix("request.")
+                .ok_or_else(|| anyhow!("unknown placeholder: {{{{{}}}}}", placeholder))?;
+            let request = ctx.request
+                .ok_or_else(|| anyhow!("no message received yet for {{{{{}}}}}", placeholder))?;
+            let pointer = format!("/{}", path.replace('.', "/"));
+
+            match request.pointer(&pointer) {
+                // strings are inserted escaped but without quotes, so
+                // they can be embedded in string literals of the mock
+                Some(JsonValue::String(s)) => {
+                    let escaped = escape_string(s);
+                    Ok(String::from(&escaped[1..escaped.len() - 1]))
+                },
+                Some(v) => Ok(v.to_string()),
+                None => Err(anyhow!("last message has no field {}", path)),
+            }
+        },
+    }
+}
+
+fn uuid_v4() -> String {
+    let mut b: [u8; 16] = rand::thread_rng().gen();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+
+    let hex: String = b.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_request_fields_and_counter() {
+        let request = JsonValue::parse("{ \"message\": \"test\", \"iteration\": 7, \"user\": { \"id\": \"u1\" } }").unwrap();
+        let ctx = TemplateContext { counter: 3, request: Some(&request) };
+        let out = expand("{ \"ack\": {{request.iteration}}, \"user\": \"{{ request.user.id }}\", \"n\": {{counter}} }", &ctx).unwrap();
+        assert_eq!(out, "{ \"ack\": 7, \"user\": \"u1\", \"n\": 3 }");
+    }
+
+    #[test]
+    fn escape_request_strings() {
+        let request = JsonValue::parse("{ \"id\": \"a\\\"b\\\\c\\nd\" }").unwrap();
+        let ctx = TemplateContext { counter: 0, request: Some(&request) };
+        let out = expand("{ \"id\": \"{{request.id}}\" }", &ctx).unwrap();
+        assert_eq!(out, "{ \"id\": \"a\\\"b\\\\c\\nd\" }");
+        assert!(JsonValue::parse(&out).is_ok());
+    }
+
+    #[test]
+    fn expand_random_values() {
+        let ctx = TemplateContext::default();
+        let n: i64 = expand("{{random_int 1 3}}", &ctx).unwrap().parse().unwrap();
+        assert!((1..=3).contains(&n));
+
+        let uuid = expand("{{uuid}}", &ctx).unwrap();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+    }
+
+    #[test]
+    fn text_without_placeholders_is_unchanged() {
+        let inp = "{ \"text\": \"a { b } c\" }";
+        assert_eq!(expand(inp, &TemplateContext::default()).unwrap(), inp);
+    }
+
+    #[test]
+    fn reject_unknown_or_missing_values() {
+        let ctx = TemplateContext::default();
+        assert!(expand("{{nope}}", &ctx).is_err());
+        assert!(expand("{{now", &ctx).is_err());
+        assert!(expand("{{request.iteration}}", &ctx).is_err());
+        assert!(expand("{{random_int 5 1}}", &ctx).is_err());
+        assert!(expand("{{random_int 0 9223372036854775807}}", &ctx).is_err());
+    }
+
+    #[test]
+    fn stub_checks_placeholders() {
+        assert_eq!(stub("{ \"n\": {{request.n}}, \"id\": \"{{uuid}}\" }").unwrap(), "{ \"n\": 0, \"id\": \"0\" }");
+        assert!(stub("{{random_int 1}}").is_err());
+        assert!(stub("{{nope}}").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Calendar fields of a point in time, in UTC.
+#[derive(Debug, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    millis: u32,
+}
+
+impl DateTime {
+    fn from_system_time(t: SystemTime) -> Self {
+        let since_epoch = t.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+        let secs = since_epoch.as_secs() as i64;
+        let days = secs.div_euclid(86_400);
+        let secs_of_day = secs.rem_euclid(86_400) as u32;
+
+        // civil_from_days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3_600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// Parses timestamps as written by `format_iso8601`. Fractional seconds are optional.
+pub fn parse_iso8601(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: i64 = date.next()?.parse().ok()?;
+    let day: i64 = date.next()?.parse().ok()?;
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':');
+    let hour: u64 = time.next()?.parse().ok()?;
+    let minute: u64 = time.next()?.parse().ok()?;
+    let second: u64 = time.next()?.parse().ok()?;
+    let millis: u64 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<3}", fraction.get(..fraction.len().min(3))?).parse().ok()?
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // days_from_civil, the inverse of the conversion in `DateTime`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    if days < 0 {
+        return None;
+    }
+
+    let secs = days as u64 * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+/// Formats `t` as an ISO 8601 timestamp in UTC, e.g. `2024-05-01T13:37:00.042Z`.
+pub fn format_iso8601(t: SystemTime) -> String {
+    let d = DateTime::from_system_time(t);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", d.year, d.month, d.day, d.hour, d.minute, d.second, d.millis)
+}
+
+/// Formats the time of day of `t` in UTC, e.g. `13:37:00Z`.
+pub fn format_clock(t: SystemTime) -> String {
+    let d = DateTime::from_system_time(t);
+    format!("{:02}:{:02}:{:02}Z", d.hour, d.minute, d.second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_epoch() {
+        assert_eq!(format_iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn format_leap_day() {
+        let t = UNIX_EPOCH + Duration::from_millis(1_709_210_096_042);
+        assert_eq!(format_iso8601(t), "2024-02-29T12:34:56.042Z");
+        assert_eq!(format_clock(t), "12:34:56Z");
+    }
+
+    #[test]
+    fn parse_formatted_timestamps() {
+        let t = UNIX_EPOCH + Duration::from_millis(1_709_210_096_042);
+        assert_eq!(parse_iso8601(&format_iso8601(t)), Some(t));
+        assert_eq!(parse_iso8601("2024-02-29T12:34:56Z"), Some(t - Duration::from_millis(42)));
+        assert_eq!(parse_iso8601("2024-02-29 12:34:56"), None);
+        assert_eq!(parse_iso8601("2024-13-01T00:00:00Z"), None);
+    }
+}
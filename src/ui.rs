@@ -1,19 +1,99 @@
-use pancurses::Window;
+use pancurses::{ColorPair, Window};
+use regex::Regex;
 
-use crate::{adapters::common::Line, parser::HELP_TEXT};
+use crate::{adapters::common::Line, binary::BinaryView, editor, filter::LineFilter, history::History, json::{JsonError, JsonFormatter, Row, TokenKind}, parser::{Parser, HELP_TEXT}, time};
 
 const CHAR_DEL: char = 0x7F as char;
 const CHAR_ESC: char = 27 as char;
 const CHAR_EOL: char = 10 as char;
+const CHAR_TAB: char = 9 as char;
+
+// color pairs for json highlighting
+const PAIR_KEY: u8 = 1;
+const PAIR_STRING: u8 = 2;
+const PAIR_NUMBER: u8 = 3;
+const PAIR_BOOL: u8 = 4;
+const PAIR_NULL: u8 = 5;
+const PAIR_ERROR: u8 = 6;
+
+// width of the direction marker in front of each line
+const PREFIX_WIDTH: usize = 3;
+// bytes of a binary message shown when not pretty printing
+const BINARY_PREVIEW: usize = 16;
+// width of the metadata labels in the detail view
+const LABEL_WIDTH: usize = 12;
+
+/// Presents lines to the user and collects commands.
+pub trait Frontend {
+
+    fn setup(&mut self) {
+    }
+
+    fn teardown(&mut self) {
+    }
+
+    fn add_line(&mut self, line: Line);
+
+    fn add_lines(&mut self, lines: Vec<Line>) {
+        for l in lines {
+            self.add_line(l);
+        }
+    }
+
+    fn add_error(&mut self, e: anyhow::Error) {
+        self.add_line(Line::new_log(format!("{}", e)));
+    }
+
+    fn print_help(&mut self) {
+        for l in HELP_TEXT.lines() {
+            self.add_line(Line::new_log(String::from(l)));
+        }
+    }
+
+    fn render(&mut self) {
+    }
+
+    /// Shows only lines matching `filter`, or all lines for `None`.
+    fn set_filter(&mut self, _: Option<LineFilter>) {
+    }
+
+    /// Names of the mocks offered by Tab completion.
+    fn set_mocks(&mut self, _: Vec<String>) {
+    }
+
+    /// Opens `content` in the user's editor and returns the edited text.
+    fn edit(&mut self, content: &str) -> anyhow::Result<String> {
+        editor::edit(&editor::editor_command(), content)
+    }
+
+    /// Returns the next command entered by the user, if any.
+    fn handle_keyboard(&mut self) -> Option<String>;
+}
 
 #[derive(Debug)]
 pub struct UI {
     pub lines: Vec<Line>,
+    // indices of the lines passing the filter, which are the ones scrolled through
+    visible: Vec<usize>,
+    filter: Option<LineFilter>,
+    search: Option<Regex>,
+    // outcome of the last search, shown in the last row
+    status: Option<String>,
+    // selected position in `visible`, none while following new lines
+    cursor: Option<usize>,
+    // index of the line shown in the detail view, and the first row shown of it
+    detail: Option<usize>,
+    detail_scroll: usize,
     dirty: bool,
     scroll_pos: u32,
     scroll_locked: bool,
+    // render json indented over multiple rows
+    pretty: bool,
+    binary_view: BinaryView,
+    colors: bool,
     win: Window,
     cli: CommandLine,
+    mocks: Vec<String>,
 }
 
 impl Default for UI {
@@ -22,82 +102,67 @@ impl Default for UI {
     }
 }
 
-impl UI {
-    pub fn new() -> Self {
-        UI{
-            lines: vec![],
-            dirty: false,
-            scroll_pos: 0,
-            scroll_locked: true,
-            win: pancurses::initscr(),
-            cli: CommandLine::default(),
-        }
-    }
-
-    pub fn setup(&mut self) {
+impl Frontend for UI {
+    fn setup(&mut self) {
         // ncurses setup
         // currently done in constructor
         //pancurses::initscr();
         pancurses::noecho();
         pancurses::cbreak();
         self.win.timeout(5);
+        // arrow keys, home and end for the command line
+        self.win.keypad(true);
+
+        if pancurses::has_colors() {
+            pancurses::start_color();
+            pancurses::use_default_colors();
+            pancurses::init_pair(PAIR_KEY as i16, pancurses::COLOR_CYAN, -1);
+            pancurses::init_pair(PAIR_STRING as i16, pancurses::COLOR_GREEN, -1);
+            pancurses::init_pair(PAIR_NUMBER as i16, pancurses::COLOR_YELLOW, -1);
+            pancurses::init_pair(PAIR_BOOL as i16, pancurses::COLOR_MAGENTA, -1);
+            pancurses::init_pair(PAIR_NULL as i16, pancurses::COLOR_RED, -1);
+            pancurses::init_pair(PAIR_ERROR as i16, pancurses::COLOR_WHITE, pancurses::COLOR_RED);
+            self.colors = true;
+        }
     }
 
-    pub fn teardown(&mut self) {
+    fn teardown(&mut self) {
         // ncurses teardown
         pancurses::endwin();
     }
 
-    pub fn add_line(&mut self, line: Line) {
+    fn add_line(&mut self, line: Line) {
+        if self.filter.as_ref().is_none_or(|f| f.matches(&line)) {
+            self.visible.push(self.lines.len());
+        }
         self.lines.push(line);
         self.dirty = true;
     }
 
-    pub fn add_lines(&mut self, lines: Vec<Line>) {
-        for l in lines {
-            self.add_line(l);
-        }
-    }
-
-    pub fn add_error(&mut self, e: anyhow::Error) {
-        self.add_line(Line::new_log(format!("{}", e)));
-    }
-
-    pub fn move_up(&mut self) {
-
-        if self.scroll_locked {
-            let max_y = self.win.get_max_y();
-            self.scroll_pos = self.lines.len()
-                .saturating_sub(max_y as usize)
-                .saturating_sub(1) as u32;
-            self.scroll_locked = false;
-        } else {
-            self.scroll_pos = self.scroll_pos.saturating_sub(1);
-            self.scroll_locked = false;
-        }
-    }
-
-    pub fn move_down(&mut self) {
-        self.scroll_pos = self.scroll_pos.saturating_add(1).min(self.lines.len() as u32);
-    }
-
-    pub fn move_to_end(&mut self) {
-        self.scroll_pos = 0;
-        self.scroll_locked = true;
+    fn set_filter(&mut self, filter: Option<LineFilter>) {
+        self.visible = (0..self.lines.len())
+            .filter(|i| filter.as_ref().is_none_or(|f| f.matches(&self.lines[*i])))
+            .collect();
+        self.filter = filter;
+        self.move_to_end();
+        self.dirty = true;
     }
 
-    pub fn move_to_start(&mut self) {
-        self.scroll_locked = false;
+    fn set_mocks(&mut self, mocks: Vec<String>) {
+        self.mocks = mocks;
     }
 
-    pub fn main_win_height(&self) -> i32 {
-        let (max_y, _) = self.win.get_max_yx();
-
-        // one line for commands
-        max_y.saturating_sub(1)
+    fn edit(&mut self, content: &str) -> anyhow::Result<String> {
+        // hand the terminal over to the editor, and take it back afterwards
+        pancurses::def_prog_mode();
+        pancurses::endwin();
+        let r = editor::edit(&editor::editor_command(), content);
+        pancurses::reset_prog_mode();
+        self.dirty = true;
+        r
     }
 
-    pub fn render(&mut self) {
+    fn render(&mut self) {
 
         if !self.dirty {
             return
@@ -106,71 +171,17 @@ impl UI {
         // TODO: do we have to clear? either way, reduce flickering
         self.win.clear();
 
-        self.render_main_win();
+        match self.detail {
+            Some(index) => self.render_detail(index),
+            None => self.render_main_win(),
+        }
         self.render_command_line();
 
         self.win.refresh();
         self.dirty = false;
     }
 
-    pub fn render_main_win(&mut self) {
-        let max_y = self.main_win_height();
-        let mut total_lines = self.lines.len();
-
-        // by default show last lineself.u
-        let mut i = self.lines.len().saturating_sub(max_y as usize);
-
-        // but when scrolling, show lines after scroll_pos
-        if !self.scroll_locked {
-            i = self.scroll_pos as usize;
-            total_lines = total_lines.min(i + max_y as usize);
-        }
-
-        while i < total_lines {
-            let l = self.lines.get(i)
-                .expect("get next line");
-
-            self.render_line(l);
-
-            i = i + 1;
-        }
-    }
-
-    fn render_line(&self, l: &Line) {
-
-        if l.treat_as_json {
-            if l.outgoing() {
-                self.win.addch('-');
-                self.win.addch('>');
-            } else {
-                self.win.addch('<');
-                self.win.addch('-');
-            }
-
-            self.win.addch(' ');
-        } else {
-            self.win.addch(' ');
-            self.win.addch(' ');
-            self.win.addch(' ');
-        }
-
-        // TODO: handle return value
-        self.win.addstr(l.text.as_str());
-        self.win.addch('\n' as u32);
-    }
-
-    pub fn render_command_line(&self) {
-        if !self.cli.has_focus {
-            return;
-        }
-
-        let (max_y, _) = self.win.get_max_yx();
-        let y = max_y.saturating_sub(1);
-
-        self.win.mvprintw(y, 0, &self.cli.command);
-    }
-
-    pub fn handle_keyboard(&mut self) -> Option<String> {
+    fn handle_keyboard(&mut self) -> Option<String> {
         match self.win.getch() {
             Some(pancurses::Input::KeyExit) => {
                 self.cli.exit();
@@ -180,6 +191,9 @@ impl UI {
                 self.cli.backspace();
                 self.dirty = true;
             },
+            Some(key) if self.cli.has_focus && self.handle_command_line_key(&key) => {
+                self.dirty = true;
+            },
             Some(pancurses::Input::Character(c)) => {
                 self.dirty = true;
                 if self.cli.has_focus {
@@ -191,17 +205,36 @@ impl UI {
                         CHAR_ESC => {
                             self.cli.exit();
                         },
+                        CHAR_TAB => {
+                            self.cli.complete_word(&self.mocks);
+                        },
                         CHAR_EOL => {
-                            return self.cli.complete();
+                            let command = self.cli.complete()?;
+                            if let Some(pattern) = command.strip_prefix('/') {
+                                self.search(pattern);
+                                return None;
+                            }
+                            return Some(command);
                         },
                         _ => {
                             self.cli.push_char(c);
                         },
                     }
+                } else if self.detail.is_some() {
+                    self.handle_detail_key(c);
                 } else {
                     match c {
-                        ':' => {
-                            self.cli.focus();
+                        CHAR_EOL => {
+                            self.open_detail();
+                        },
+                        ':' | '/' => {
+                            self.cli.focus(c);
+                        },
+                        'n' => {
+                            self.jump_to_match(true, false);
+                        },
+                        'N' => {
+                            self.jump_to_match(false, false);
                         },
                         'g' => {
                             self.move_to_start();
@@ -215,6 +248,12 @@ impl UI {
                         'k' => {
                             self.move_up();
                         },
+                        'p' => {
+                            self.pretty = !self.pretty;
+                        },
+                        'x' => {
+                            self.binary_view = self.binary_view.toggle();
+                        },
                         _ => {},
                     }
                 }
@@ -225,33 +264,518 @@ impl UI {
         
         None
     }
+}
 
-    pub fn print_help(&mut self) {
-        for l in HELP_TEXT.lines() {
-            self.add_line(Line::new_log(String::from(l)));
+impl UI {
+    pub fn new() -> Self {
+        UI{
+            lines: vec![],
+            visible: vec![],
+            filter: None,
+            search: None,
+            status: None,
+            cursor: None,
+            detail: None,
+            detail_scroll: 0,
+            dirty: false,
+            scroll_pos: 0,
+            scroll_locked: true,
+            pretty: false,
+            binary_view: BinaryView::default(),
+            colors: false,
+            win: pancurses::initscr(),
+            cli: CommandLine::new(History::default_path().map(History::load).unwrap_or_default()),
+            mocks: vec![],
+        }
+    }
+
+    /// Cursor movement and history. Returns false for keys it does not handle.
+    fn handle_command_line_key(&mut self, key: &pancurses::Input) -> bool {
+        match key {
+            pancurses::Input::KeyLeft => self.cli.move_left(),
+            pancurses::Input::KeyRight => self.cli.move_right(),
+            pancurses::Input::KeyHome => self.cli.move_home(),
+            pancurses::Input::KeyEnd => self.cli.move_end(),
+            pancurses::Input::KeyDC => self.cli.delete(),
+            pancurses::Input::KeyUp => self.cli.browse_history(true),
+            pancurses::Input::KeyDown => self.cli.browse_history(false),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn move_up(&mut self) {
+        if self.visible.is_empty() {
+            return;
+        }
+
+        // the first move selects the last line
+        let pos = match self.cursor {
+            Some(c) => c.saturating_sub(1),
+            None => self.visible.len() - 1,
+        };
+        self.select(pos);
+    }
+
+    pub fn move_down(&mut self) {
+        if let Some(c) = self.cursor {
+            self.select((c + 1).min(self.visible.len().saturating_sub(1)));
+        }
+    }
+
+    /// Stops selecting and follows new lines again.
+    pub fn move_to_end(&mut self) {
+        self.cursor = None;
+        self.scroll_pos = 0;
+        self.scroll_locked = true;
+    }
+
+    pub fn move_to_start(&mut self) {
+        if !self.visible.is_empty() {
+            self.cursor = Some(0);
+        }
+        self.scroll_pos = 0;
+        self.scroll_locked = false;
+    }
+
+    /// Moves the cursor to `pos`, scrolling no further than needed to show it.
+    fn select(&mut self, pos: usize) {
+        let mut first = self.first_shown();
+        self.cursor = Some(pos);
+        self.scroll_locked = false;
+
+        if pos < first {
+            self.scroll_pos = pos as u32;
+            return;
+        }
+
+        let max_y = self.main_win_height() as usize;
+        let width = self.win.get_max_x().max(1) as usize;
+        let mut height: usize = (first..=pos)
+            .map(|i| self.line_height(&self.lines[self.visible[i]], width))
+            .sum();
+        while height > max_y && first < pos {
+            height -= self.line_height(&self.lines[self.visible[first]], width);
+            first += 1;
+        }
+        self.scroll_pos = first as u32;
+    }
+
+    fn open_detail(&mut self) {
+        let pos = self.cursor.unwrap_or(self.visible.len().saturating_sub(1));
+        if let Some(index) = self.visible.get(pos) {
+            self.detail = Some(*index);
+            self.detail_scroll = 0;
+        }
+    }
+
+    fn handle_detail_key(&mut self, c: char) {
+        match c {
+            CHAR_ESC | CHAR_EOL | 'q' => {
+                self.detail = None;
+            },
+            'j' => {
+                self.detail_scroll += 1;
+            },
+            'k' => {
+                self.detail_scroll = self.detail_scroll.saturating_sub(1);
+            },
+            'g' => {
+                self.detail_scroll = 0;
+            },
+            'G' => {
+                // clamped to the last page when rendering
+                self.detail_scroll = usize::MAX;
+            },
+            'x' => {
+                self.binary_view = self.binary_view.toggle();
+            },
+            _ => {},
+        }
+    }
+
+    fn search(&mut self, pattern: &str) {
+        if pattern.is_empty() {
+            self.search = None;
+            self.status = None;
+            return;
+        }
+
+        match Regex::new(pattern) {
+            Ok(re) => {
+                self.search = Some(re);
+                self.jump_to_match(true, true);
+            },
+            Err(e) => {
+                self.status = Some(format!("invalid pattern: {}", e));
+            },
+        }
+    }
+
+    /// Scrolls the next or previous line matching the search to the top,
+    /// wrapping around at either end.
+    fn jump_to_match(&mut self, forward: bool, include_first: bool) {
+        let Some(ref re) = self.search else {
+            return;
+        };
+        let len = self.visible.len();
+        let start = self.first_shown();
+        let is_match = |pos: usize| re.is_match(&self.lines[self.visible[pos]].text);
+
+        let mut offset = if include_first { 0 } else { 1 };
+        let mut found = None;
+        while offset <= len && len > 0 {
+            let pos = if forward {
+                (start + offset) % len
+            } else {
+                (start + len * 2 - offset) % len
+            };
+            if is_match(pos) {
+                found = Some(pos);
+                break;
+            }
+            offset += 1;
+        }
+
+        self.status = match found {
+            Some(pos) => {
+                let total = (0..len).filter(|p| is_match(*p)).count();
+                let nth = (0..=pos).filter(|p| is_match(*p)).count();
+                Some(format!("/{}  match {} of {}", re, nth, total))
+            },
+            None => Some(format!("pattern not found: {}", re)),
+        };
+        if let Some(pos) = found {
+            self.cursor = Some(pos);
+            self.scroll_pos = pos as u32;
+            self.scroll_locked = false;
+        }
+    }
+
+    pub fn main_win_height(&self) -> i32 {
+        let (max_y, _) = self.win.get_max_yx();
+
+        // one line for commands
+        max_y.saturating_sub(1)
+    }
+
+    /// Position in `visible` of the first line on screen.
+    fn first_shown(&self) -> usize {
+        // when scrolling, show lines after scroll_pos
+        if !self.scroll_locked {
+            return self.scroll_pos as usize;
+        }
+
+        // by default show as many of the last lines as fit
+        let max_y = self.main_win_height();
+        let width = self.win.get_max_x().max(1) as usize;
+        let mut i = self.visible.len();
+        let mut height = 0;
+        while i > 0 {
+            height += self.line_height(&self.lines[self.visible[i - 1]], width);
+            if height > max_y as usize {
+                break;
+            }
+            i -= 1;
+        }
+
+        i
+    }
+
+    pub fn render_main_win(&mut self) {
+        let max_y = self.main_win_height();
+        let mut i = self.first_shown();
+
+        while i < self.visible.len() && self.win.get_cur_y() < max_y {
+            let l = self.lines.get(self.visible[i])
+                .expect("get next line");
+
+            self.render_line(l, max_y, self.cursor == Some(i));
+
+            i += 1;
+        }
+    }
+
+    fn line_rows(&self, l: &Line) -> Vec<Row> {
+        if let Some(ref bytes) = l.binary {
+            if !self.pretty {
+                let preview = format!("{}: {}", l.text, self.binary_view.preview(bytes, BINARY_PREVIEW));
+                return vec![vec![(TokenKind::Punctuation, preview)]];
+            }
+            let mut rows = vec![vec![(TokenKind::Punctuation, l.text.clone())]];
+            for r in self.binary_view.rows(bytes) {
+                rows.push(vec![(TokenKind::String, r)]);
+            }
+            return rows;
+        }
+
+        if l.treat_as_json {
+            JsonFormatter.highlight(&l.text, self.pretty)
+        } else {
+            vec![vec![(TokenKind::Punctuation, l.text.clone())]]
+        }
+    }
+
+    /// Number of screen rows `l` takes up, including wrapped rows.
+    fn line_height(&self, l: &Line, width: usize) -> usize {
+        let conn_width = l.conn.as_ref().map(|c| c.to_string().len() + 3).unwrap_or(0);
+        let rows: usize = self.line_rows(l).iter()
+            .map(|r| {
+                let len: usize = r.iter().map(|(_, t)| t.chars().count()).sum();
+                (PREFIX_WIDTH + conn_width + len).div_ceil(width).max(1)
+            })
+            .sum();
+
+        match l.json_error {
+            Some(ref e) => rows + (PREFIX_WIDTH + conn_width + UI::error_text(e).chars().count()).div_ceil(width),
+            None => rows,
+        }
+    }
+
+    fn error_text(e: &JsonError) -> String {
+        format!("invalid json: {}", e)
+    }
+
+    fn color_pair(kind: TokenKind) -> Option<u8> {
+        match kind {
+            TokenKind::Key => Some(PAIR_KEY),
+            TokenKind::String => Some(PAIR_STRING),
+            TokenKind::Number => Some(PAIR_NUMBER),
+            TokenKind::Bool => Some(PAIR_BOOL),
+            TokenKind::Null => Some(PAIR_NULL),
+            TokenKind::Punctuation => None,
+        }
+    }
+
+    fn render_line(&self, l: &Line, max_y: i32, selected: bool) {
+
+        if selected {
+            self.win.attron(pancurses::A_REVERSE);
+        }
+        if l.is_message() {
+            if l.outgoing() {
+                self.win.addch('-');
+                self.win.addch('>');
+            } else {
+                self.win.addch('<');
+                self.win.addch('-');
+            }
+
+            // mark messages which failed validation
+            if l.invalid_json {
+                self.win.addch('!');
+            } else {
+                self.win.addch(' ');
+            }
+        } else if l.control {
+            if l.outgoing() {
+                self.win.addstr("*> ");
+            } else {
+                self.win.addstr("<* ");
+            }
+        } else {
+            self.win.addch(' ');
+            self.win.addch(' ');
+            self.win.addch(' ');
+        }
+        if selected {
+            self.win.attroff(pancurses::A_REVERSE);
+        }
+
+        let mut prefix_width = PREFIX_WIDTH;
+        if let Some(ref conn) = l.conn {
+            let conn = format!("[{}] ", conn);
+            prefix_width += conn.len();
+            self.win.addstr(conn);
+        }
+
+        for (i, row) in self.line_rows(l).iter().enumerate() {
+            if self.win.get_cur_y() >= max_y {
+                break;
+            }
+
+            if i > 0 {
+                self.win.addstr(" ".repeat(prefix_width));
+            }
+
+            self.render_row(row);
+        }
+
+        if let Some(ref e) = l.json_error {
+            if self.win.get_cur_y() < max_y {
+                self.win.addstr(" ".repeat(prefix_width));
+                if self.colors {
+                    self.win.attron(ColorPair(PAIR_ERROR));
+                }
+                self.win.addstr(UI::error_text(e));
+                if self.colors {
+                    self.win.attroff(ColorPair(PAIR_ERROR));
+                }
+                self.win.addch('\n' as u32);
+            }
         }
     }
+
+    fn render_row(&self, row: &Row) {
+        for (kind, text) in row {
+            match UI::color_pair(*kind).filter(|_| self.colors) {
+                Some(pair) => {
+                    self.win.attron(ColorPair(pair));
+                    // TODO: handle return value
+                    self.addstr_highlighted(text);
+                    self.win.attroff(ColorPair(pair));
+                },
+                None => {
+                    self.addstr_highlighted(text);
+                },
+            }
+        }
+        self.win.addch('\n' as u32);
+    }
+
+    /// Metadata of `l` followed by its whole payload, pretty printed.
+    fn detail_rows(&self, l: &Line) -> Vec<Row> {
+        let field = |label: &str, value: String| {
+            vec![(TokenKind::Key, format!("{:<1$}", label, LABEL_WIDTH)), (TokenKind::Punctuation, value)]
+        };
+
+        let mut rows = vec![field("time", time::format_iso8601(l.timestamp))];
+        if l.is_message() || l.control {
+            let dir = if l.outgoing() { "outgoing" } else { "incoming" };
+            let kind = if l.control { "control" } else if l.binary.is_some() { "binary message" } else { "message" };
+            rows.push(field("direction", format!("{} {}", dir, kind)));
+        }
+        if let Some(ref conn) = l.conn {
+            rows.push(field("connection", conn.to_string()));
+        }
+
+        let payload = l.raw.as_ref().unwrap_or(&l.text);
+        let size = l.binary.as_ref().map(|b| b.len()).unwrap_or(payload.len());
+        rows.push(field("size", format!("{} bytes", size)));
+        if let Some(ref e) = l.json_error {
+            rows.push(field("json", format!("invalid, {}", e)));
+        } else if l.treat_as_json {
+            rows.push(field("json", String::from("valid")));
+        }
+        rows.push(vec![]);
+
+        if let Some(ref bytes) = l.binary {
+            rows.extend(self.binary_view.rows(bytes).into_iter().map(|r| vec![(TokenKind::String, r)]));
+        } else if l.treat_as_json {
+            rows.extend(JsonFormatter.highlight(&l.text, true));
+        } else {
+            rows.extend(payload.lines().map(|r| vec![(TokenKind::Punctuation, String::from(r))]));
+        }
+
+        rows
+    }
+
+    fn render_detail(&mut self, index: usize) {
+        let max_y = self.main_win_height();
+        let rows = self.detail_rows(&self.lines[index]);
+        self.detail_scroll = self.detail_scroll.min(rows.len().saturating_sub(max_y as usize));
+
+        for row in rows.iter().skip(self.detail_scroll) {
+            if self.win.get_cur_y() >= max_y {
+                break;
+            }
+            self.render_row(row);
+        }
+    }
+
+    /// Writes `text`, showing matches of the search in reverse video.
+    fn addstr_highlighted(&self, text: &str) {
+        let Some(ref re) = self.search else {
+            self.win.addstr(text);
+            return;
+        };
+
+        let mut last = 0;
+        for m in re.find_iter(text).filter(|m| !m.is_empty()) {
+            self.win.addstr(&text[last..m.start()]);
+            self.win.attron(pancurses::A_REVERSE);
+            self.win.addstr(m.as_str());
+            self.win.attroff(pancurses::A_REVERSE);
+            last = m.end();
+        }
+        self.win.addstr(&text[last..]);
+    }
+
+    /// The command being typed, or else the active filter and search.
+    pub fn render_command_line(&self) {
+        let (max_y, _) = self.win.get_max_yx();
+        let y = max_y.saturating_sub(1);
+
+        if self.cli.has_focus {
+            self.win.mvprintw(y, 0, &self.cli.command);
+            self.win.mv(y, self.cli.cursor_column());
+            return;
+        }
+        if self.detail.is_some() {
+            self.win.mvprintw(y, 0, "j/k scroll, g/G top/bottom, x hex/base64, Esc close");
+            return;
+        }
+
+        let mut parts = vec![];
+        if let Some(ref f) = self.filter {
+            parts.push(format!("filter: {} ({} of {} lines)", f, self.visible.len(), self.lines.len()));
+        }
+        if let Some(ref status) = self.status {
+            parts.push(status.clone());
+        }
+        if !parts.is_empty() {
+            self.win.mvprintw(y, 0, parts.join("  |  "));
+        }
+    }
+
 }
 
 
+/// Cycles through Tab completion candidates for the word at `start`.
+#[derive(Debug)]
+struct Completion {
+    start: usize,
+    candidates: Vec<String>,
+    index: usize,
+    // text after the cursor, kept as it is
+    tail: String,
+}
+
 #[derive(Default, Debug)]
 struct CommandLine {
     has_focus: bool,
     command: String,
+    // byte offset of the cursor in `command`, always after the prefix
+    pos: usize,
+    history: History,
+    // entry shown while browsing the history, and the command typed before
+    history_pos: Option<usize>,
+    draft: String,
+    completion: Option<Completion>,
 }
 
 impl CommandLine {
+    fn new(history: History) -> Self {
+        CommandLine { history, ..Default::default() }
+    }
+
     pub fn push_char(&mut self, c: char) {
-        self.command.push(c);
+        self.command.insert(self.pos, c);
+        self.pos += c.len_utf8();
+        self.completion = None;
     }
 
     pub fn blur(&mut self) {
         self.has_focus = false;
+        self.history_pos = None;
+        self.completion = None;
     }
 
-    pub fn focus(&mut self) {
+    /// Starts a command with `prefix`, `:` for commands or `/` for searching.
+    pub fn focus(&mut self, prefix: char) {
         self.has_focus = true;
-        self.command = String::from(":")
+        self.command = String::from(prefix);
+        self.pos = self.command.len();
     }
 
     pub fn backspace(&mut self) {
@@ -259,26 +783,209 @@ impl CommandLine {
             return
         }
 
-        if self.command.len() > 1 {
-            self.command.pop();
-        } else {
+        self.completion = None;
+        if self.command.len() <= 1 {
             self.blur();
+        } else if self.pos > 1 {
+            self.move_left();
+            self.command.remove(self.pos);
         }
     }
 
+    pub fn delete(&mut self) {
+        if self.pos < self.command.len() {
+            self.command.remove(self.pos);
+            self.completion = None;
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        // the prefix stays in front of the cursor
+        if let Some(c) = self.command[1.min(self.pos)..self.pos].chars().next_back() {
+            self.pos -= c.len_utf8();
+        }
+        self.completion = None;
+    }
+
+    pub fn move_right(&mut self) {
+        if let Some(c) = self.command[self.pos..].chars().next() {
+            self.pos += c.len_utf8();
+        }
+        self.completion = None;
+    }
+
+    pub fn move_home(&mut self) {
+        self.pos = 1.min(self.command.len());
+        self.completion = None;
+    }
+
+    pub fn move_end(&mut self) {
+        self.pos = self.command.len();
+        self.completion = None;
+    }
+
+    /// Shows the previous (`back`) or next history entry of the same kind,
+    /// commands or searches. Going past the last one restores what was typed.
+    pub fn browse_history(&mut self, back: bool) {
+        let prefix = self.command.chars().next().unwrap_or(':');
+        let entries = &self.history.entries;
+        let current = self.history_pos.unwrap_or(entries.len());
+
+        let next = if back {
+            entries[..current].iter().rposition(|e| e.starts_with(prefix))
+        } else {
+            entries.iter().enumerate()
+                .skip(current + 1)
+                .find(|(_, e)| e.starts_with(prefix))
+                .map(|(i, _)| i)
+        };
+
+        match next {
+            Some(i) => {
+                if self.history_pos.is_none() {
+                    self.draft = self.command.clone();
+                }
+                self.history_pos = Some(i);
+                self.command = entries[i].clone();
+            },
+            None if !back && self.history_pos.is_some() => {
+                self.history_pos = None;
+                self.command = std::mem::take(&mut self.draft);
+            },
+            None => return,
+        }
+        self.move_end();
+    }
+
+    /// Completes the word before the cursor. The longest common prefix of all
+    /// candidates is inserted first, further presses cycle through them.
+    pub fn complete_word(&mut self, mocks: &[String]) {
+        if let Some(ref mut c) = self.completion {
+            c.index = (c.index + 1) % c.candidates.len();
+            let candidate = &c.candidates[c.index];
+            self.command = format!("{}{}{}", &self.command[..c.start], candidate, c.tail);
+            self.pos = c.start + candidate.len();
+            return;
+        }
+
+        let (start, candidates) = Parser::complete(&self.command[..self.pos], mocks);
+        let typed = self.pos - start;
+        let tail = self.command[self.pos..].to_string();
+
+        let mut common = match candidates.first() {
+            Some(c) => c.clone(),
+            None => return,
+        };
+        for c in candidates.iter().skip(1) {
+            let len = common.char_indices()
+                .zip(c.chars())
+                .find(|((_, a), b)| a != b)
+                .map(|((i, _), _)| i)
+                .unwrap_or(common.len().min(c.len()));
+            common.truncate(len);
+        }
+
+        if candidates.len() == 1 && tail.is_empty() {
+            common.push(' ');
+        }
+        if candidates.len() > 1 && common.len() == typed {
+            // nothing left in common, start cycling with the first candidate
+            common = candidates[0].clone();
+            self.completion = Some(Completion { start, candidates, index: 0, tail: tail.clone() });
+        }
+
+        self.command = format!("{}{}{}", &self.command[..start], common, tail);
+        self.pos = start + common.len();
+    }
+
     pub fn exit(&mut self) {
         self.command = String::new();
+        self.pos = 0;
         self.blur();
     }
 
     pub fn complete(&mut self) -> Option<String> {
-        if self.command.len() > 0 {
+        if !self.command.is_empty() {
             let mut command = String::new();
             std::mem::swap(&mut command, &mut self.command);
+            self.pos = 0;
             self.blur();
+            if command.len() > 1 {
+                self.history.push(&command);
+            }
             return Some(command);
         }
 
         None
     }
+
+    /// Column of the cursor on screen.
+    fn cursor_column(&self) -> i32 {
+        self.command[..self.pos].chars().count() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str) -> CommandLine {
+        let mut cli = CommandLine::default();
+        cli.focus(':');
+        text.chars().for_each(|c| cli.push_char(c));
+        cli
+    }
+
+    #[test]
+    fn edit_at_cursor() {
+        let mut cli = typed("sned");
+        cli.move_home();
+        cli.move_left();
+        cli.move_right();
+        cli.delete();
+        cli.move_right();
+        cli.push_char('n');
+        assert_eq!(cli.command, ":send");
+        cli.move_home();
+        cli.backspace();
+        assert_eq!(cli.command, ":send");
+    }
+
+    #[test]
+    fn browse_history_of_same_kind() {
+        let mut cli = CommandLine::default();
+        for entry in [":ls", "/error", ":send a.json"] {
+            cli.history.push(entry);
+        }
+
+        cli.focus(':');
+        cli.push_char('x');
+        cli.browse_history(true);
+        assert_eq!(cli.command, ":send a.json");
+        cli.browse_history(true);
+        assert_eq!(cli.command, ":ls");
+        cli.browse_history(true);
+        assert_eq!(cli.command, ":ls");
+        cli.browse_history(false);
+        cli.browse_history(false);
+        assert_eq!(cli.command, ":x");
+    }
+
+    #[test]
+    fn complete_common_prefix_then_cycle() {
+        let mocks = vec![String::from("login.json"), String::from("logout.json")];
+        let mut cli = typed("se");
+        cli.complete_word(&mocks);
+        assert_eq!(cli.command, ":send ");
+
+        cli.push_char('l');
+        cli.complete_word(&mocks);
+        assert_eq!(cli.command, ":send log");
+        cli.complete_word(&mocks);
+        assert_eq!(cli.command, ":send login.json");
+        cli.complete_word(&mocks);
+        assert_eq!(cli.command, ":send logout.json");
+        assert_eq!(cli.complete(), Some(String::from(":send logout.json")));
+        assert_eq!(cli.history.entries, vec![":send logout.json"]);
+    }
 }